@@ -0,0 +1,70 @@
+//! Records which build of the types shared with computer libraries this crate is compiled
+//! against, see `computer_layout_hash`.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Packages whose types cross the boundary to computer libraries by raw pointer.
+const SHARED_PACKAGES: [&str; 2] = ["stellar-bit-core", "egui"];
+
+fn main() {
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".into());
+    let rustc_version = Command::new(rustc)
+        .arg("-V")
+        .output()
+        .ok()
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .unwrap_or_default();
+
+    let mut build = vec![rustc_version.trim().to_string()];
+    match find_lockfile() {
+        Some(path) => {
+            println!("cargo:rerun-if-changed={}", path.display());
+            let lockfile = std::fs::read_to_string(&path).unwrap_or_default();
+            build.extend(locked_packages(&lockfile));
+        }
+        None => println!(
+            "cargo:warning=no Cargo.lock found, computer libraries are only told apart by compiler"
+        ),
+    }
+    println!("cargo:rustc-env=STELLAR_BIT_COMPUTER_BUILD={}", build.join("; "));
+    println!("cargo:rerun-if-env-changed=RUSTC");
+}
+
+/// The lockfile of the workspace being built. That is the client's own one, or the one of a
+/// computer depending on it, which sits above the target directory holding `OUT_DIR`.
+fn find_lockfile() -> Option<PathBuf> {
+    let out_dir = PathBuf::from(std::env::var_os("OUT_DIR")?);
+    let manifest_dir = PathBuf::from(std::env::var_os("CARGO_MANIFEST_DIR")?);
+    out_dir
+        .ancestors()
+        .chain(manifest_dir.ancestors())
+        .map(|dir| dir.join("Cargo.lock"))
+        .find(|path| path.is_file() && locks_shared_packages(path))
+}
+
+fn locks_shared_packages(path: &Path) -> bool {
+    std::fs::read_to_string(path).is_ok_and(|lockfile| !locked_packages(&lockfile).is_empty())
+}
+
+/// `name version source` of every shared package in the lockfile.
+fn locked_packages(lockfile: &str) -> Vec<String> {
+    let mut packages = vec![];
+    for entry in lockfile.split("[[package]]").skip(1) {
+        let field = |key: &str| {
+            entry.lines().find_map(|line| {
+                let value = line.strip_prefix(key)?.trim().strip_prefix('=')?.trim();
+                Some(value.trim_matches('"').to_string())
+            })
+        };
+        let Some(name) = field("name") else {
+            continue;
+        };
+        if SHARED_PACKAGES.contains(&name.as_str()) {
+            let version = field("version").unwrap_or_default();
+            let source = field("source").unwrap_or_default();
+            packages.push(format!("{} {} {}", name, version, source));
+        }
+    }
+    packages
+}
@@ -6,14 +6,32 @@ use self::particles::{ParticleSystem, ShrinkingCircle};
 
 use super::*;
 
+/// Version of the contract between the client and computers, for libraries and wasm
+/// modules alike. Bump whenever the signature or meaning of an exported symbol changes.
+pub const COMPUTER_ABI_VERSION: u32 = 1;
+
+#[cfg(not(target_arch = "wasm32"))]
 pub mod controller_select;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod controller_process;
+#[cfg(not(target_arch = "wasm32"))]
+mod controller_wasmtime;
 
+#[cfg(not(target_arch = "wasm32"))]
 use futures::Future;
-use stellar_bit_central_hub_api::{HubAPI, ServerDetails, UserData};
-use controller_select::Controller;
+#[cfg(not(target_arch = "wasm32"))]
+use controller_select::{ComputerSpec, Controller};
+use crate::network::stats::kilobytes;
 
 mod camera;
 use camera::Camera;
+pub use camera::CameraConfig;
+
+mod replay;
+use replay::Replay;
+
+mod smoothing;
+use smoothing::Smoothing;
 
 mod particles;
 mod sounds;
@@ -22,6 +40,7 @@ use ellipsoid::prelude::egui_file::FileDialog;
 use ellipsoid::prelude::Textures;
 use rand::random;
 use sounds::SoundManager;
+pub use sounds::SoundConfig;
 
 use serde::{Serialize, Deserialize};
 
@@ -34,6 +53,7 @@ use log::warn;
 use rodio::OutputStream;
 use strum::IntoEnumIterator;
 use enum_bytes::{enum_bytes};
+#[cfg(not(target_arch = "wasm32"))]
 use tokio::runtime::Runtime;
 
 const FRIENDLY_COLOR: Color = Color::from_rgb(0. / 255., 186. / 255., 130. / 255.);
@@ -115,36 +135,113 @@ impl Drawable for Projectile {
     }
 }
 
+/// Startup options of the windowed client, set before the window is created.
+#[derive(Clone, Debug, Default)]
+pub struct AppOptions {
+    /// Join string, `"address access_token user_id"`.
+    pub join: Option<String>,
+    /// Record the session to this file.
+    pub record: Option<PathBuf>,
+    /// Play back a recording instead of connecting anywhere.
+    pub replay: Option<PathBuf>,
+    pub sync_policy: SyncPolicy,
+    /// Compare every full game with the local one, see `DesyncOptions`.
+    pub desync: Option<DesyncOptions>,
+    /// Address of a server to watch without a player slot, see `NetworkConnection::spectate`.
+    pub spectate: Option<String>,
+    /// The hub the "Central Hub" window logs into.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub hub: HubBackend,
+    /// Prefilled in the "Network connection" window.
+    pub server_addr: Option<String>,
+    /// Prefilled in the "Central Hub" window.
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Where "Remember me" saves the login.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub credentials: Option<CredentialStore>,
+    /// Unlocks the saved login on start, which then logs in right away.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub passphrase: Option<String>,
+    /// Loaded on start, in this order. The web client takes its computer from JS instead.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub computers: Vec<ComputerSpec>,
+    pub camera: CameraConfig,
+    pub sound: SoundConfig,
+}
+
+static APP_OPTIONS: std::sync::OnceLock<AppOptions> = std::sync::OnceLock::new();
+
+pub(crate) fn set_options(options: AppOptions) {
+    if APP_OPTIONS.set(options).is_err() {
+        warn!("App options were already set, ignoring the new ones");
+    }
+}
+
 struct AppIntervals {
-    cmds_sync: Interval,
-    game_sync: Interval,
+    #[cfg(not(target_arch = "wasm32"))]
     hub_servers: Interval
 }
 
 struct EguiFields {
+    #[cfg(not(target_arch = "wasm32"))]
     computer_file_dialog: Option<FileDialog>,
     world_file_dialog: Option<FileDialog>,
     server_addr: String,
     world_name: String,
+    #[cfg(not(target_arch = "wasm32"))]
     username: String,
+    #[cfg(not(target_arch = "wasm32"))]
     password: String,
-    hub_servers: Arc<Mutex<Vec<ServerDetails>>>,
+    /// Save the login once it succeeds.
+    #[cfg(not(target_arch = "wasm32"))]
+    remember: bool,
+    /// Encrypts the saved login, or unlocks it.
+    #[cfg(not(target_arch = "wasm32"))]
+    passphrase: String,
+    #[cfg(not(target_arch = "wasm32"))]
+    hub_error: Option<String>,
+    #[cfg(not(target_arch = "wasm32"))]
+    hub_servers: Arc<Mutex<Vec<HubServer>>>,
     access_token: String,
-    user_id: i64
+    user_id: i64,
+    #[cfg(not(target_arch = "wasm32"))]
+    computer_error: Option<String>,
+    connection_error: Option<String>,
+    recording_error: Option<String>,
+    /// Scope text being edited for each computer, by name.
+    #[cfg(not(target_arch = "wasm32"))]
+    computer_scopes: std::collections::HashMap<String, String>
 }
 
 impl Default for EguiFields {
     fn default() -> Self {
         Self {
+            #[cfg(not(target_arch = "wasm32"))]
             computer_file_dialog: None,
             world_file_dialog: None,
             server_addr: "ws://0.0.0.0:39453".into(),
             world_name: format!("world_{}", random::<u32>()),
+            #[cfg(not(target_arch = "wasm32"))]
             username: String::new(),
+            #[cfg(not(target_arch = "wasm32"))]
             password: String::new(),
+            #[cfg(not(target_arch = "wasm32"))]
+            remember: false,
+            #[cfg(not(target_arch = "wasm32"))]
+            passphrase: String::new(),
+            #[cfg(not(target_arch = "wasm32"))]
+            hub_error: None,
+            #[cfg(not(target_arch = "wasm32"))]
             hub_servers: Arc::new(Mutex::new(vec![])),
             access_token: String::new(),
-            user_id: 0
+            user_id: 0,
+            #[cfg(not(target_arch = "wasm32"))]
+            computer_error: None,
+            connection_error: None,
+            recording_error: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            computer_scopes: Default::default()
         }
     }
 }
@@ -162,6 +259,8 @@ fn integer_edit_field(ui: &mut egui::Ui, value: &mut i64) -> egui::Response {
 pub struct SpacecraftApp {
     pub graphics: Graphics<Txts>,
     pub network_msgs: Vec<ClientRequest>,
+    /// Commands of the computers by their names, sent with the next `network_msgs`.
+    computer_cmds: Vec<(String, Vec<GameCmd>)>,
     network_connection: Option<NetworkConnection>,
     game: Arc<RwLock<Game>>,
     user: Arc<RwLock<User>>,
@@ -175,20 +274,39 @@ pub struct SpacecraftApp {
     egui_fields: EguiFields,
     sound_manager: SoundManager,
     physical_shapes: Vec<Shape<Txts>>,
-    hub_conn: Option<Arc<HubAPI>>,
-    rt: Runtime
+    #[cfg(not(target_arch = "wasm32"))]
+    hub_conn: Option<Arc<dyn HubClient>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    hub: HubBackend,
+    #[cfg(not(target_arch = "wasm32"))]
+    credentials: Option<CredentialStore>,
+    camera_config: CameraConfig,
+    sound_config: SoundConfig,
+    #[cfg(not(target_arch = "wasm32"))]
+    rt: Runtime,
+    replay: Option<Replay>,
+    record_path: Option<PathBuf>,
+    sync_policy: SyncPolicy,
+    desync: Option<DesyncOptions>,
+    smoothing: Smoothing
 }
 
 
 impl App<Txts> for SpacecraftApp {
     fn new(window: winit::window::Window) -> Self {
 
+        #[cfg(not(target_arch = "wasm32"))]
         let rt = tokio::runtime::Builder::new_multi_thread()
             .enable_all()
             .build()
             .unwrap();
 
+        #[cfg(not(target_arch = "wasm32"))]
         let graphics = rt.block_on(Graphics::new(window));
+        // nothing can wait for a promise on the browser's thread, this only finishes when
+        // the adapter and device are ready right away, like with WebGL
+        #[cfg(target_arch = "wasm32")]
+        let graphics = futures::executor::block_on(Graphics::new(window));
 
 
         let mut init_game = Game::new();
@@ -208,63 +326,102 @@ impl App<Txts> for SpacecraftApp {
 
         let (_audio_stream, _audio_stream_handle) = OutputStream::try_default().unwrap();
 
-        let cmd_matches = clap::Command::new("Stellar Bit Client")
-            .version("1.0")
-            .author("Your Name")
-            .about("A client for Stellar Bit")
-            .arg(
-                clap::Arg::new("join")
-                    .long("join")
-                    .value_name("ADDRESS")
-                    .help("This is only for copy-pasting to speed up the proccess of joining a server. The format is \"address access_token user_id\".")
-            )
-            .get_matches();
-
-        let server_access_raw = cmd_matches.get_one::<String>("join");
-
-        let network_connection = server_access_raw.map(|data| {
-            let mut data = data.split_whitespace();
-            let addr = data.next().unwrap();
-            let acc_token = data.next().unwrap();
-            let user_id = data.next().unwrap().parse::<i64>().unwrap();
-
-            
-            let network_connection_res = block_on(&rt, NetworkConnection::start(addr.into(), game.clone(), user.clone()));
-            match network_connection_res {
-                Ok(mut network_connection) => {
-                    network_connection.sync_clock();
-                    network_connection.send(ClientRequest::Join(user_id as u64, acc_token.into()));
-                    network_connection.send(ClientRequest::FullGameSync);
-                    println!("Successfully connected to server {:?}!", addr);
-                    network_connection
-                }
-                Err(e) => panic!("Error when trying to connect to server {:?}! ({:?})", addr, e)
+        let options = APP_OPTIONS.get().cloned().unwrap_or_default();
+
+        let join = options.join.as_deref().map(parse_join_string).transpose();
+        let (join, join_error) = match join {
+            Ok(join) => (join.map(|(addr, acc_token, user_id)| (addr, Some((user_id, acc_token)))), None),
+            Err(e) => {
+                eprintln!("Invalid join string: {}", e);
+                (None, Some(format!("Invalid join string: {}", e)))
+            }
+        };
+        let connect_to = join.or_else(|| options.spectate.clone().map(|addr| (addr, None)));
+
+        let replay = options.replay.as_ref().and_then(|path| match Replay::load(path) {
+            Ok(mut replay) => {
+                replay.seek(
+                    time::Duration::ZERO,
+                    &mut game.write().unwrap(),
+                    &mut user.write().unwrap(),
+                );
+                println!("Replaying {:?}", path);
+                Some(replay)
+            }
+            Err(e) => {
+                eprintln!("Failed to load recording at {:?} ({})!", path, e);
+                None
             }
         });
 
-        Self {
+        let mut app = Self {
             game,
             user,
-            network_connection,
+            network_connection: None,
             time_intervals: AppIntervals {
-                cmds_sync: Interval::new(time::Duration::from_millis(300)),
-                game_sync: Interval::new(time::Duration::from_millis(3000)),
+                #[cfg(not(target_arch = "wasm32"))]
                 hub_servers: Interval::new_elapsed(time::Duration::from_secs(30))
             },
             controller: Controller::new(),
             follow_target: None,
             mouse_position: vec2(0.0, 0.0),
-            camera: Camera::new(-10., 1.0),
+            camera: Camera::new((-10f32).clamp(options.camera.min_zoom, options.camera.max_zoom), 1.0),
             particle_system: ParticleSystem::new(),
             graphics,
             network_msgs: vec![],
+            computer_cmds: vec![],
             right_mouse_pressed: false,
             egui_fields: EguiFields::default(),
             sound_manager: SoundManager::new(),
             physical_shapes: vec![],
+            #[cfg(not(target_arch = "wasm32"))]
             hub_conn: None,
-            rt
+            #[cfg(not(target_arch = "wasm32"))]
+            hub: options.hub,
+            #[cfg(not(target_arch = "wasm32"))]
+            credentials: options.credentials,
+            camera_config: options.camera,
+            sound_config: options.sound,
+            #[cfg(not(target_arch = "wasm32"))]
+            rt,
+            replay,
+            record_path: options.record,
+            sync_policy: options.sync_policy,
+            desync: options.desync,
+            smoothing: Smoothing::new()
+        };
+        if let Some(server_addr) = options.server_addr {
+            app.egui_fields.server_addr = server_addr;
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            app.egui_fields.username = options.username.unwrap_or_default();
+            app.egui_fields.password = options.password.unwrap_or_default();
+            if let Some(passphrase) = options.passphrase
+                && app.credentials.as_ref().is_some_and(CredentialStore::exists)
+            {
+                app.egui_fields.passphrase = passphrase;
+                app.unlock_saved_login();
+            }
+            else if app.credentials.as_ref().is_some_and(CredentialStore::is_remembered) {
+                app.unlock_saved_login();
+            }
         }
+        #[cfg(not(target_arch = "wasm32"))]
+        for spec in options.computers {
+            let path = spec.path.clone();
+            if let Err(e) = app.controller.add_computer(spec) {
+                eprintln!("Failed to load computer at {:?} ({})!", path, e);
+                app.egui_fields.computer_error = Some(e.to_string());
+            }
+        }
+        if let Some((addr, join)) = connect_to {
+            app.connect(addr, join);
+        }
+        if join_error.is_some() {
+            app.egui_fields.connection_error = join_error;
+        }
+        app
     }
 
     fn graphics(&self) -> &Graphics<Txts> {
@@ -284,6 +441,8 @@ impl App<Txts> for SpacecraftApp {
 
         self.update_network();
 
+        self.smoothing.update(dt);
+
         self.process_events();
     }
 
@@ -302,13 +461,13 @@ impl App<Txts> for SpacecraftApp {
         if let WindowEvent::MouseWheel { delta, .. } = event {
             match delta {
                 winit::event::MouseScrollDelta::LineDelta(_, y) => {
-                    self.camera.zoom += y * 0.1;
+                    self.camera.zoom += y * self.camera_config.scroll_speed;
                 }
                 winit::event::MouseScrollDelta::PixelDelta(pos) => {
-                    self.camera.zoom += pos.y as f32 / 1000.;
+                    self.camera.zoom += pos.y as f32 * self.camera_config.scroll_speed / 100.;
                 }
             }
-            self.camera.zoom = self.camera.zoom.min(-4.0).max(-15.0);
+            self.camera.zoom = self.camera.zoom.min(self.camera_config.max_zoom).max(self.camera_config.min_zoom);
 
             return true;
         } else if let WindowEvent::MouseInput { state, button, .. } = event {
@@ -352,6 +511,11 @@ impl App<Txts> for SpacecraftApp {
 
 impl SpacecraftApp {
     fn update_main(&mut self, _dt: f32) {
+        if let Some(replay) = &mut self.replay {
+            replay.advance(&mut self.game.write().unwrap(), &mut self.user.write().unwrap());
+            return;
+        }
+
         let user = self.user();
         let mut game = self.game.write().unwrap();
 
@@ -365,19 +529,165 @@ impl SpacecraftApp {
         let game_dt = now() - game.sync.last_update;
         game.update(game_dt.as_secs_f32());
 
-        if self.time_intervals.cmds_sync.check() {
-            self.network_msgs.push(ClientRequest::GameCmdsSync);
+        if let Some(network_connection) = &mut self.network_connection {
+            self.controller.report_feedback(network_connection.take_send_feedback());
         }
-        if self.time_intervals.game_sync.check() {
-            self.network_msgs.push(ClientRequest::FullGameSync);
+        let computer_cmds =
+            self.controller
+                .retrieve_cmds_by_computer(&mut game, &user, &self.graphics.egui_platform.context());
+        // the server ignores commands of spectators anyway
+        if matches!(user, User::Player(_)) {
+            self.computer_cmds.extend(computer_cmds);
         }
+    }
 
-        let network_game_cmds =
-            self.controller
-                .retrieve_cmds(&mut game, &user, &self.graphics.egui_platform.context());
-        if !network_game_cmds.is_empty() {
-            self.network_msgs
-                .push(ClientRequest::ExecuteGameCmds(network_game_cmds));
+    /// Logs into the hub with the login in the "Central Hub" window, and saves it if
+    /// "Remember me" is checked, without a passphrase it unlocks on its own on the next
+    /// start but is only obfuscated. A failure is shown in the window.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn log_in(&mut self) {
+        let credentials = Credentials {
+            username: self.egui_fields.username.clone(),
+            password: self.egui_fields.password.clone(),
+        };
+        match self.hub.connect(self.rt.handle(), credentials.username.clone(), credentials.password.clone()) {
+            Ok(hub_conn) => {
+                println!("Successfully connected to central hub!");
+                self.hub_conn = Some(hub_conn);
+                self.egui_fields.hub_error = None;
+            }
+            Err(err) => {
+                eprintln!("Error when trying to connect to central hub: {}", err);
+                self.egui_fields.hub_error = Some(err);
+                return;
+            }
+        }
+        if self.egui_fields.remember
+            && let Some(store) = &self.credentials
+            && let Err(e) = match self.egui_fields.passphrase.is_empty() {
+                true => store.remember(&credentials),
+                false => store.save(&credentials, &self.egui_fields.passphrase),
+            }
+        {
+            eprintln!("Failed to save the login to {:?}: {}", store.path(), e);
+            self.egui_fields.hub_error = Some(format!("Couldn't save the login: {}", e));
+        }
+        self.egui_fields.passphrase.clear();
+    }
+
+    /// Decrypts the saved login with the entered passphrase, or without one if it was
+    /// remembered without, and logs in with it.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn unlock_saved_login(&mut self) {
+        let Some(store) = &self.credentials else {
+            return;
+        };
+        let saved = match self.egui_fields.passphrase.is_empty() && store.is_remembered() {
+            true => store.unlock(),
+            false => store.load(&self.egui_fields.passphrase),
+        };
+        match saved {
+            Ok(credentials) => {
+                self.egui_fields.username = credentials.username;
+                self.egui_fields.password = credentials.password;
+                self.egui_fields.remember = false;
+                self.log_in();
+            }
+            Err(e) => {
+                eprintln!("Failed to unlock the saved login in {:?}: {}", store.path(), e);
+                self.egui_fields.hub_error = Some(format!("Couldn't unlock the saved login: {}", e));
+            }
+        }
+    }
+
+    /// The "Central Hub" window, logging in and unlocking the saved login.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn central_hub_window(&mut self) {
+        let mut log_in = false;
+        let mut unlock = false;
+        let saved_login = self.credentials.as_ref().is_some_and(CredentialStore::exists);
+        egui::Window::new("Central Hub").show(&self.graphics.egui_platform.context(), |ui| {
+            if let Some(hub_conn) = &self.hub_conn {
+                ui.label(format!("Logged in as '{}'", hub_conn.username()));
+                if saved_login && ui.button("Forget saved login").clicked()
+                    && let Some(store) = &self.credentials
+                    && let Err(e) = store.forget()
+                {
+                    eprintln!("Failed to delete the saved login at {:?}: {}", store.path(), e);
+                }
+            }
+            else {
+                ui.label("Username");
+                ui.text_edit_singleline(&mut self.egui_fields.username);
+                ui.label("Password");
+                egui::TextEdit::singleline(&mut self.egui_fields.password).password(true).show(ui);
+                if self.credentials.is_some() {
+                    ui.checkbox(&mut self.egui_fields.remember, "Remember me");
+                }
+                if saved_login || self.egui_fields.remember {
+                    ui.label(match self.egui_fields.remember {
+                        true => "Passphrase, without one the login is only obfuscated",
+                        false => "Passphrase of the saved login",
+                    });
+                    egui::TextEdit::singleline(&mut self.egui_fields.passphrase).password(true).show(ui);
+                }
+                ui.horizontal(|ui| {
+                    log_in = ui.button("Log In").clicked();
+                    unlock = saved_login && ui.button("Use saved login").clicked();
+                });
+                if let Some(hub_error) = &self.egui_fields.hub_error {
+                    ui.colored_label(egui::Color32::RED, hub_error);
+                }
+            }
+        });
+        if log_in {
+            self.log_in();
+        }
+        else if unlock {
+            self.unlock_saved_login();
+        }
+    }
+
+    /// Connects with the app's sync, desync and recording options. Joins with `join`, given
+    /// as user id and access token, and spectates without it. A failure is shown in the
+    /// "Network connection" window.
+    fn connect(&mut self, server_addr: String, join: Option<(i64, String)>) {
+        self.egui_fields.server_addr = server_addr.clone();
+        if let Some((user_id, access_token)) = &join {
+            self.egui_fields.user_id = *user_id;
+            self.egui_fields.access_token = access_token.clone();
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        let network_connection_res = block_on(&self.rt, NetworkConnection::start(server_addr.clone(), self.game.clone(), self.user.clone()));
+        // nothing can block on the web, a failed attempt shows up as a reconnect there
+        #[cfg(target_arch = "wasm32")]
+        let network_connection_res = Ok::<_, ConnectionError>(NetworkConnection::start_in_background(
+            server_addr.clone(),
+            self.game.clone(),
+            self.user.clone(),
+        ));
+        match network_connection_res {
+            Ok(mut network_connection) => {
+                network_connection.set_sync_policy(self.sync_policy.clone());
+                network_connection.set_desync_check(self.desync.clone());
+                if let Some(path) = &self.record_path
+                    && let Err(e) = start_recording(&mut network_connection, path)
+                {
+                    eprintln!("{}", e);
+                    self.egui_fields.recording_error = Some(e);
+                }
+                match join {
+                    Some((user_id, access_token)) => network_connection.join(user_id as u64, access_token),
+                    None => network_connection.spectate(),
+                }
+                self.network_connection = Some(network_connection);
+                self.egui_fields.connection_error = None;
+                println!("Successfully connected to server {:?}!", server_addr);
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                self.egui_fields.connection_error = Some(e.to_string());
+            }
         }
     }
 
@@ -405,13 +715,21 @@ impl SpacecraftApp {
     }
 
     fn update_network(&mut self) {
+        let Some(network_connection) = &mut self.network_connection else {
+            self.network_msgs.clear();
+            self.computer_cmds.clear();
+            return;
+        };
+        network_connection.poll();
+        self.smoothing.add(network_connection.take_corrections());
+        for (computer, cmds) in std::mem::take(&mut self.computer_cmds) {
+            network_connection.send_cmds(&computer, cmds);
+        }
         let requests = std::mem::take(&mut self.network_msgs);
         if requests.len() == 0 {
             return;
         }
-        if let Some(network_connection) = &mut self.network_connection {
-            network_connection.send_multiple(requests);
-        }
+        network_connection.send_multiple(requests);
     }
 
 
@@ -449,9 +767,9 @@ impl SpacecraftApp {
             //     .min(1.);
 
             let zoom_eff = 1. / (-self.camera.mp().y.log2());
-            let dist_eff = 1. - distance.powi(2) / 100_000. / (-self.camera.mp().y.log2());
+            let dist_eff = 1. - distance.powi(2) / self.sound_config.distance_falloff / (-self.camera.mp().y.log2());
             let volume = zoom_eff * dist_eff;
-            let volume = volume.max(0.).min(1.);
+            let volume = volume.max(0.).min(1.) * self.sound_config.volume;
             self.sound_manager.play(sound.into(), volume);
         }
     }
@@ -508,123 +826,128 @@ impl SpacecraftApp {
         let user = self.user();
         let game = self.game.read().unwrap();
 
-        for asteroid in game.asteroids() {
-            let gtransform = GTransform::from_translation(asteroid.body.position)
-                .rotate(asteroid.body.rotation);
-
-            self.physical_shapes.push(asteroid.shape().apply(gtransform).set_z(ASTEROID_Z));
-            self.physical_shapes.push(
-                asteroid
-                    .shape()
-                    .apply(gtransform.inflate_fixed(0.1))
-                    .set_color(Color::from_rgb(0.5, 0.5, 0.5))
-                    .reset_texture()
-                    .set_z(OUTLINE_Z)
-            );
-        }
-        for spacecraft in game.spacecrafts() {
-            let spacecraft_gtransform = GTransform::from_translation(spacecraft.body.position)
-                .rotate(spacecraft.body.rotation)
-                .translate(-spacecraft.center_of_mass);
-
-            let outline_color = if let User::Player(id) = user {
-                if id == spacecraft.owner {
-                    FRIENDLY_COLOR
-                } else {
-                    ENEMY_COLOR
+        for (object_id, game_object) in &game.game_objects {
+            // drawn where the smoothing has it, the game keeps the corrected position
+            let offset = self.smoothing.offset(object_id);
+            match game_object {
+                GameObject::Asteroid(asteroid) => {
+                    let gtransform = GTransform::from_translation(asteroid.body.position + offset)
+                        .rotate(asteroid.body.rotation);
+
+                    self.physical_shapes.push(asteroid.shape().apply(gtransform).set_z(ASTEROID_Z));
+                    self.physical_shapes.push(
+                        asteroid
+                            .shape()
+                            .apply(gtransform.inflate_fixed(0.1))
+                            .set_color(Color::from_rgb(0.5, 0.5, 0.5))
+                            .reset_texture()
+                            .set_z(OUTLINE_Z)
+                    );
                 }
-            } else {
-                ENEMY_COLOR
-            };
-
-            for component in spacecraft.components.values() {
-                let rotation = match component {
-                    Component::Weapon(weapon) => weapon.rotation,
-                    _ => 0.,
-                };
-
-                let outline_thickness = 0.1;
-
-                let gtransform = spacecraft_gtransform
-                    .translate(component.body().position.as_vec2())
-                    .rotate(component.body().orientation.to_radians() + rotation)
-                    .translate(-Vec2::ONE * 0.5)
-                    .stretch(component.body().scale().as_vec2());
-
-                let outline_gtransform = spacecraft_gtransform
-                    .translate(component.body().position.as_vec2())
-                    .rotate(component.body().orientation.to_radians() + rotation)
-                    .translate(-Vec2::ONE * (0.5 + outline_thickness / 2.))
-                    .stretch(component.body().scale().as_vec2() + outline_thickness);
-
-                let texture = match component.body().origin {
-                    ComponentType::KineticWeapon => Txts::KineticWeaponComponent,
-                    ComponentType::Central => Txts::CentralBlockComponent,
-                    ComponentType::MissileLauncher => Txts::MissileWeaponComponent,
-                    ComponentType::RaptorEngine => Txts::RaptorEngineComponent,
-                    ComponentType::SteelBlock => Txts::SteelBlockComponent,
-                };
-
-                let component_shape = Shape::from_square()
-                    .apply(gtransform)
-                    .set_texture(texture)
-                    .set_z(if component.body().top().is_none() {
-                        SPACECRAFT_Z
+                GameObject::Spacecraft(spacecraft) => {
+                    let spacecraft_gtransform = GTransform::from_translation(spacecraft.body.position + offset)
+                        .rotate(spacecraft.body.rotation)
+                        .translate(-spacecraft.center_of_mass);
+
+                    let outline_color = if let User::Player(id) = user {
+                        if id == spacecraft.owner {
+                            FRIENDLY_COLOR
+                        } else {
+                            ENEMY_COLOR
+                        }
                     } else {
-                        TOP_COMPONENT_Z
-                    });
-
-                self.physical_shapes.push(component_shape);
-                if component.body().top().is_none() {
-                    let component_outline = Shape::from_square()
-                        .apply(outline_gtransform)
-                        .set_color(outline_color)
-                        .set_z(OUTLINE_Z)
-                        .reset_texture();
-                    self.physical_shapes.push(component_outline);
+                        ENEMY_COLOR
+                    };
 
-                    let component_grad = Shape::from_square().apply(gtransform).set_texture(Txts::CircleGrad).set_z(GRAD_Z);
-                    self.physical_shapes.push(component_grad);
+                    for component in spacecraft.components.values() {
+                        let rotation = match component {
+                            Component::Weapon(weapon) => weapon.rotation,
+                            _ => 0.,
+                        };
+
+                        let outline_thickness = 0.1;
+
+                        let gtransform = spacecraft_gtransform
+                            .translate(component.body().position.as_vec2())
+                            .rotate(component.body().orientation.to_radians() + rotation)
+                            .translate(-Vec2::ONE * 0.5)
+                            .stretch(component.body().scale().as_vec2());
+
+                        let outline_gtransform = spacecraft_gtransform
+                            .translate(component.body().position.as_vec2())
+                            .rotate(component.body().orientation.to_radians() + rotation)
+                            .translate(-Vec2::ONE * (0.5 + outline_thickness / 2.))
+                            .stretch(component.body().scale().as_vec2() + outline_thickness);
+
+                        let texture = match component.body().origin {
+                            ComponentType::KineticWeapon => Txts::KineticWeaponComponent,
+                            ComponentType::Central => Txts::CentralBlockComponent,
+                            ComponentType::MissileLauncher => Txts::MissileWeaponComponent,
+                            ComponentType::RaptorEngine => Txts::RaptorEngineComponent,
+                            ComponentType::SteelBlock => Txts::SteelBlockComponent,
+                        };
+
+                        let component_shape = Shape::from_square()
+                            .apply(gtransform)
+                            .set_texture(texture)
+                            .set_z(if component.body().top().is_none() {
+                                SPACECRAFT_Z
+                            } else {
+                                TOP_COMPONENT_Z
+                            });
+
+                        self.physical_shapes.push(component_shape);
+                        if component.body().top().is_none() {
+                            let component_outline = Shape::from_square()
+                                .apply(outline_gtransform)
+                                .set_color(outline_color)
+                                .set_z(OUTLINE_Z)
+                                .reset_texture();
+                            self.physical_shapes.push(component_outline);
+
+                            let component_grad = Shape::from_square().apply(gtransform).set_texture(Txts::CircleGrad).set_z(GRAD_Z);
+                            self.physical_shapes.push(component_grad);
+                        }
+                    }
                 }
-            }
-        }
-        for star_base in game.star_bases() {
-            let gtransform = GTransform::from_translation(star_base.body.position);
+                GameObject::StarBase(star_base) => {
+                    let gtransform = GTransform::from_translation(star_base.body.position + offset);
+
+                    let outline_color = if let User::Player(id) = user {
+                        if id == star_base.owner {
+                            FRIENDLY_COLOR
+                        } else {
+                            ENEMY_COLOR
+                        }
+                    } else {
+                        ENEMY_COLOR
+                    };
 
-            let outline_color = if let User::Player(id) = user {
-                if id == star_base.owner {
-                    FRIENDLY_COLOR
-                } else {
-                    ENEMY_COLOR
+                    self.physical_shapes.push(
+                        star_base
+                            .shape()
+                            .apply(gtransform)
+                            .set_z(STAR_BASE_Z)
+                    );
+                    self.physical_shapes.push(
+                        star_base
+                            .shape()
+                            .apply(gtransform.inflate_fixed(0.05))
+                            .set_z(OUTLINE_Z)
+                            .reset_texture()
+                            .set_color(outline_color)
+                    )
                 }
-            } else {
-                ENEMY_COLOR
-            };
+                GameObject::Projectile(projectile) => {
+                    let gtransform = GTransform::from_translation(projectile.body.position + offset)
+                        .rotate(projectile.body.rotation)
+                        .stretch(projectile.size);
 
-            self.physical_shapes.push(
-                star_base
-                    .shape()
-                    .apply(gtransform)
-                    .set_z(STAR_BASE_Z)
-            );
-            self.physical_shapes.push(
-                star_base
-                    .shape()
-                    .apply(gtransform.inflate_fixed(0.05))
-                    .set_z(OUTLINE_Z)
-                    .reset_texture()
-                    .set_color(outline_color)
-            )
-        }
-
-        for projectile in game.projectiles() {
-            let gtransform = GTransform::from_translation(projectile.body.position)
-                .rotate(projectile.body.rotation)
-                .stretch(projectile.size);
-
-            let shape = projectile.shape().set_z(PROJECTILE_Z).apply(gtransform);
+                    let shape = projectile.shape().set_z(PROJECTILE_Z).apply(gtransform);
 
-            self.physical_shapes.push(shape);
+                    self.physical_shapes.push(shape);
+                }
+            }
         }
 
         // for game_object in game.game_objects.values() {
@@ -686,7 +1009,8 @@ impl SpacecraftApp {
     fn draw_egui(&mut self) {
         let user = self.user();
         let mut game = self.game.write().unwrap();
-
+        // connecting needs all of `self`, so it waits until the windows are drawn
+        let mut connect_to = None;
 
         if let Some(follow_target) = self.follow_target {
             let game_object = game.game_objects.get(&follow_target).unwrap();
@@ -767,6 +1091,25 @@ impl SpacecraftApp {
                 }
                 User::Spectator => {
                     ui.label("Spectator");
+                    let mut players = game.players.iter().collect::<Vec<_>>();
+                    players.sort_by_key(|(id, _)| **id);
+                    for (player_id, player) in players {
+                        let owned = game.game_objects.iter().filter(|(_, go)| go.owner() == Some(*player_id));
+                        let star_bases = owned.clone().filter(|(_, go)| matches!(go, GameObject::StarBase(_))).count();
+                        let spacecrafts = owned.clone().filter(|(_, go)| matches!(go, GameObject::Spacecraft(_))).count();
+                        ui.collapsing(format!("Player {} ({} star bases, {} spacecrafts)", player_id, star_bases, spacecrafts), |ui| {
+                            ui.label(format!("Materials: {:?}", player.materials));
+                            let mut owned = owned.collect::<Vec<_>>();
+                            owned.sort_by_key(|(id, _)| **id);
+                            for (id, game_object) in owned {
+                                let game_object_text: &'static str = game_object.into();
+                                if ui.button(format!("{} [{}]", game_object_text, id)).clicked() {
+                                    self.follow_target = Some(*id);
+                                    self.camera.offset = Vec2::ZERO;
+                                }
+                            }
+                        });
+                    }
                 }
             }
         });
@@ -779,59 +1122,163 @@ impl SpacecraftApp {
             });
         });
 
-        egui::Window::new("Network connection").show(&self.graphics.egui_platform.context(), |ui| {
-            if let Some(network_connection) = &self.network_connection {
-                ui.label(format!("{}", network_connection.server_addr));
-                if ui.button("Disconnect").clicked() {
-                    self.network_connection = None;
+        if let Some(replay) = &mut self.replay {
+            egui::Window::new("Replay").show(&self.graphics.egui_platform.context(), |ui| {
+                ui.horizontal(|ui| {
+                    if ui.button(if replay.playing { "Pause" } else { "Play" }).clicked() {
+                        replay.playing = !replay.playing;
+                    }
+                    ui.add(egui::Slider::new(&mut replay.speed, 0.1..=16.).logarithmic(true).text("Speed"));
+                });
+                let mut position = replay.position().as_secs_f32();
+                let duration = replay.duration().as_secs_f32();
+                let timeline = ui.add(
+                    egui::Slider::new(&mut position, 0.0..=duration)
+                        .text(format!("/ {:.0} s", duration))
+                        .suffix(" s"),
+                );
+                if timeline.changed() {
+                    replay.seek(time::Duration::from_secs_f32(position), &mut game, &mut self.user.write().unwrap());
                 }
-            }
-            else {
-                ui.text_edit_singleline(&mut self.egui_fields.server_addr);
-                integer_edit_field(ui, &mut self.egui_fields.user_id);
-                egui::TextEdit::singleline(&mut self.egui_fields.access_token).password(true).show(ui);
-                if ui.button("Join").clicked() {
-                    let network_connection_res = block_on(&self.rt, NetworkConnection::start(self.egui_fields.server_addr.clone(), self.game.clone(), self.user.clone()));
-                    match network_connection_res {
-                        Ok(mut network_connection) => {
-                            network_connection.sync_clock();
-                            network_connection.send(ClientRequest::Join(self.egui_fields.user_id as u64, self.egui_fields.access_token.clone()));
-                            network_connection.send(ClientRequest::FullGameSync);
-                            self.network_connection = Some(network_connection);
-                            println!("Successfully connected to server {:?}!", self.egui_fields.server_addr);
+            });
+        }
+
+        // joining a server would fight with the replay over the game
+        if self.replay.is_none() {
+            egui::Window::new("Network connection").show(&self.graphics.egui_platform.context(), |ui| {
+                if let Some(network_connection) = &mut self.network_connection {
+                    ui.label(format!("{}", network_connection.server_addr));
+                    ui.label(format!("State: {}", network_connection.state()));
+                    ui.label(format!("Unacknowledged commands: {}", network_connection.pending_cmds()));
+                    ui.label(format!("Queued commands: {}", network_connection.queued_cmds()));
+                    ui.label(format!("Clock: {}", network_connection.clock_stats()));
+                    ui.label(format!("Syncing {}", network_connection.sync_status()));
+                    if let Some(desync_status) = network_connection.desync_status() {
+                        ui.label(format!("Desync check: {}", desync_status));
+                    }
+                    if let Some(error) = network_connection.last_error() {
+                        ui.colored_label(egui::Color32::RED, error.to_string());
+                    }
+                    if matches!(
+                        network_connection.state(),
+                        ConnectionState::Failed(_) | ConnectionState::Kicked(_)
+                    )
+                        && ui.button("Reconnect").clicked()
+                    {
+                        network_connection.reconnect();
+                    }
+                    if network_connection.is_recording() {
+                        if ui.button("Stop recording").clicked() {
+                            network_connection.stop_recording();
                         }
-                        Err(e) => eprintln!("Error when trying to connect to server {:?}! ({:?})", self.egui_fields.server_addr, e)
+                    }
+                    else if ui.button("Record").clicked() {
+                        let path = Path::new("recordings/");
+                        let result = std::fs::create_dir_all(path)
+                            .map_err(|e| format!("Failed to create {:?} ({})!", path, e))
+                            .and_then(|_| start_recording(network_connection, &path.join(format!("session_{}.sbrec", now().as_secs()))));
+                        self.egui_fields.recording_error = result.err();
+                    }
+                    if let Some(recording_error) = &self.egui_fields.recording_error {
+                        ui.colored_label(egui::Color32::RED, recording_error);
+                    }
+                    if ui.button("Disconnect").clicked() {
+                        self.network_connection = None;
                     }
                 }
-                if let Some(hub_conn) = &self.hub_conn {
-                    if self.time_intervals.hub_servers.check() {
-                        let servers_c = self.egui_fields.hub_servers.clone();
-                        let hub_conn_c = hub_conn.clone();
-                        self.rt.spawn(async move {
-                            let servers = hub_conn_c.servers().await;
-                            *servers_c.lock().unwrap() = servers;
-                        });
+                else {
+                    ui.text_edit_singleline(&mut self.egui_fields.server_addr);
+                    integer_edit_field(ui, &mut self.egui_fields.user_id);
+                    egui::TextEdit::singleline(&mut self.egui_fields.access_token).password(true).show(ui);
+                    ui.horizontal(|ui| {
+                        if ui.button("Join").clicked() {
+                            let join = (self.egui_fields.user_id, self.egui_fields.access_token.clone());
+                            connect_to = Some((self.egui_fields.server_addr.clone(), Some(join)));
+                        }
+                        if ui.button("Spectate").clicked() {
+                            connect_to = Some((self.egui_fields.server_addr.clone(), None));
+                        }
+                    });
+                    if let Some(connection_error) = &self.egui_fields.connection_error {
+                        ui.colored_label(egui::Color32::RED, connection_error);
                     }
-                    egui::CollapsingHeader::new("Public servers").default_open(true).show(ui, |ui| {
-                        let servers = self.egui_fields.hub_servers.lock().unwrap();
-                        for server in servers.iter() {
-                            let button = egui::Button::new(&server.name);
-                            if let Some(addr) = &server.addr {
-                                if ui.add_enabled(true, button).clicked() {
-                                    let server_acc = block_on(&self.rt, hub_conn.access_server(server.id));
-                                    self.egui_fields.user_id = block_on(&self.rt, hub_conn.my_user_data()).id;
-                                    self.egui_fields.server_addr = "ws://".to_string() + &server_acc.server_addr;
-                                    self.egui_fields.access_token = server_acc.access_token;
+                    #[cfg(not(target_arch = "wasm32"))]
+                    if let Some(hub_conn) = &self.hub_conn {
+                        if self.time_intervals.hub_servers.check() {
+                            let servers_c = self.egui_fields.hub_servers.clone();
+                            let hub_conn_c = hub_conn.clone();
+                            self.rt.spawn_blocking(move || match hub_conn_c.servers() {
+                                Ok(servers) => *servers_c.lock().unwrap() = servers,
+                                Err(e) => eprintln!("Failed to get the servers from the central hub: {}", e),
+                            });
+                        }
+                        egui::CollapsingHeader::new("Public servers").default_open(true).show(ui, |ui| {
+                            let servers = self.egui_fields.hub_servers.lock().unwrap();
+                            for server in servers.iter() {
+                                let button = egui::Button::new(&server.name);
+                                if let Some(addr) = &server.addr {
+                                    ui.horizontal(|ui| {
+                                        if ui.add_enabled(true, button).clicked() {
+                                            match hub_conn.access_server(server.id).and_then(|server_acc| Ok((server_acc, hub_conn.user_id()?))) {
+                                                Ok((server_acc, user_id)) => {
+                                                    self.egui_fields.user_id = user_id;
+                                                    self.egui_fields.server_addr = "ws://".to_string() + &server_acc.server_addr;
+                                                    self.egui_fields.access_token = server_acc.access_token;
+                                                }
+                                                Err(e) => {
+                                                    eprintln!("Failed to access server '{}': {}", server.name, e);
+                                                    self.egui_fields.connection_error = Some(e);
+                                                }
+                                            }
+                                        }
+                                        // watching needs no access token
+                                        if ui.button("Spectate").clicked() {
+                                            connect_to = Some(("ws://".to_string() + addr, None));
+                                        }
+                                    });
+                                }
+                                else {
+                                    ui.add_enabled(false, button);
                                 }
                             }
-                            else {
-                                ui.add_enabled(false, button);
+                        });
+                    }
+                }
+            });
+        }
+
+        if let Some(network_connection) = &self.network_connection {
+            let stats = network_connection.stats();
+            egui::Window::new("Network stats").default_open(false).show(&self.graphics.egui_platform.context(), |ui| {
+                let (sent, received) = (stats.total_sent(), stats.total_received());
+                ui.label(format!("Sent: {} messages, {}", sent.messages, kilobytes(sent.bytes)));
+                ui.label(format!("Received: {} messages, {}", received.messages, kilobytes(received.bytes)));
+                ui.label(format!("Round trip: {:.1} ms", stats.rtt.as_secs_f32() * 1000.));
+                ui.label(format!("Slow downs: {}", stats.slow_downs));
+                ui.label(format!(
+                    "Full syncs: {} (max {})",
+                    kilobytes(stats.last_full_sync_bytes as u64),
+                    kilobytes(stats.max_full_sync_bytes as u64)
+                ));
+                stats_graph(ui, "Sent bytes/s", &stats.history.sent_bytes);
+                stats_graph(ui, "Received bytes/s", &stats.history.received_bytes);
+                stats_graph(ui, "Round trip ms", &stats.history.rtt_ms);
+                stats_graph(ui, "Full sync bytes", &stats.history.full_sync_bytes);
+                ui.collapsing("Per message", |ui| {
+                    egui::Grid::new("network_stats_messages").striped(true).show(ui, |ui| {
+                        for (direction, counts) in [("Sent", &stats.sent), ("Received", &stats.received)] {
+                            for (name, count) in counts {
+                                ui.label(direction);
+                                ui.label(*name);
+                                ui.label(count.messages.to_string());
+                                ui.label(kilobytes(count.bytes));
+                                ui.end_row();
                             }
                         }
                     });
-                }
-            }
-        });
+                });
+            });
+        }
 
         egui::Window::new("Worlds").show(&self.graphics.egui_platform.context(), |ui| {
             ui.horizontal(|ui| {
@@ -867,9 +1314,11 @@ impl SpacecraftApp {
             }
         });
 
+        #[cfg(not(target_arch = "wasm32"))]
         egui::Window::new("Controller").show(&self.graphics.egui_platform.context(), |ui| {
-            if ui.button("Load").clicked() {
-                let mut dialog = FileDialog::open_file(self.controller.computer_path());
+            if ui.button("Add computer").clicked() {
+                let last_path = self.controller.slots().last().map(|slot| slot.path().to_owned());
+                let mut dialog = FileDialog::open_file(last_path);
                 dialog.open();
                 self.egui_fields.computer_file_dialog = Some(dialog);
             }
@@ -879,48 +1328,121 @@ impl SpacecraftApp {
                     .selected()
                 {
                     if let Some(file) = dialog.path() {
-                        let computer_path = file.as_os_str().to_str().unwrap().into();
-                        self.controller.select_computer(computer_path);
+                        let mut spec = ComputerSpec::new(file.to_owned());
+                        spec.name = self.controller.unique_name(&spec.name);
+                        match self.controller.add_computer(spec) {
+                            Ok(()) => self.egui_fields.computer_error = None,
+                            Err(e) => {
+                                eprintln!("Failed to load computer at {:?} ({})!", file, e);
+                                self.egui_fields.computer_error = Some(e.to_string());
+                            }
+                        }
                     }
                 }
             }
+            if let Some(computer_error) = &self.egui_fields.computer_error {
+                ui.colored_label(egui::Color32::RED, computer_error);
+            }
 
-            ui.label(format!(
-                "Active computer: {}",
-                self.controller
-                    .computer_path()
-                    .unwrap_or("None".into())
-                    .as_os_str()
-                    .to_str()
-                    .unwrap()
-            ));
-        });
+            ui.checkbox(&mut self.controller.hot_reload, "Reload when the file changes");
+            let mut isolated = self.controller.isolated();
+            if ui.checkbox(&mut isolated, "Run in separate process").changed() {
+                if let Err(e) = self.controller.set_isolated(isolated) {
+                    eprintln!("Failed to reload computer ({})!", e);
+                    self.egui_fields.computer_error = Some(e.to_string());
+                }
+            }
+            let mut limit_ms = self.controller.budget.limit.as_millis() as u64;
+            if ui
+                .add(egui::Slider::new(&mut limit_ms, 1..=500).text("Budget (ms)"))
+                .changed()
+            {
+                self.controller.budget.limit = Duration::from_millis(limit_ms);
+            }
+            let abandoned = controller_select::abandoned_computer_threads();
+            if abandoned > 0 {
+                ui.colored_label(
+                    egui::Color32::RED,
+                    format!("{} hung computer threads left behind", abandoned),
+                );
+            }
 
-        egui::Window::new("Central Hub").show(&self.graphics.egui_platform.context(), |ui| {
-            if let Some(hub_conn) = &self.hub_conn {
-                ui.label(format!("Logged in as '{}'", hub_conn.username));
+            if self.controller.slots().is_empty() {
+                ui.label("No computers loaded");
             }
-            else {
-                ui.label("Username");
-                ui.text_edit_singleline(&mut self.egui_fields.username);
-                ui.label("Password");
-                egui::TextEdit::singleline(&mut self.egui_fields.password).password(true).show(ui);
-                if ui.button("Log In").clicked() {
-                    match block_on(&self.rt, HubAPI::connect(self.egui_fields.username.clone(), self.egui_fields.password.clone())) {
-                        Ok(hub_conn) => {
-                            println!("Successfully connected to central hub!");
-                            self.hub_conn = Some(Arc::new(hub_conn));
-                        }
-                        Err(err) => {
-                            eprintln!("Error when trying to connect to central hub: {:?}", err);
-                        }
+            let isolated = self.controller.isolated();
+            let slot_count = self.controller.slots().len();
+            let mut remove = None;
+            let mut reorder = None;
+            for (i, slot) in self.controller.slots_mut().iter_mut().enumerate() {
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut slot.enabled, slot.name.as_str());
+                    if ui.add_enabled(i > 0, egui::Button::new("Up")).clicked() {
+                        reorder = Some((i, i - 1));
+                    }
+                    if ui.add_enabled(i + 1 < slot_count, egui::Button::new("Down")).clicked() {
+                        reorder = Some((i, i + 1));
+                    }
+                    if ui.button("Remove").clicked() {
+                        remove = Some(i);
+                    }
+                });
+                ui.label(format!("File: {}", slot.path().display()));
+
+                let scope = self
+                    .egui_fields
+                    .computer_scopes
+                    .entry(slot.name.clone())
+                    .or_insert_with(|| slot.scope.clone().unwrap_or_default().join(", "));
+                ui.horizontal(|ui| {
+                    ui.label("Scope tags");
+                    if ui.text_edit_singleline(scope).changed() {
+                        let tags = scope
+                            .split(',')
+                            .map(|tag| tag.trim().to_owned())
+                            .filter(|tag| !tag.is_empty())
+                            .collect::<Vec<_>>();
+                        slot.scope = (!tags.is_empty()).then_some(tags);
+                    }
+                });
+
+                if let Some(reload_status) = &slot.reload_status {
+                    ui.label(reload_status);
+                }
+                if isolated || slot.restarts > 0 {
+                    ui.label(format!("Restarts: {}", slot.restarts));
+                }
+                let stats = slot.stats().clone();
+                ui.label(format!("Last execution: {:.2} ms", stats.last.as_secs_f32() * 1000.));
+                ui.label(format!("Slowest execution: {:.2} ms", stats.max.as_secs_f32() * 1000.));
+                ui.label(format!("Budget overruns: {}", stats.overruns));
+                if stats.suspended {
+                    ui.colored_label(
+                        egui::Color32::RED,
+                        "Suspended: the computer kept exceeding its budget",
+                    );
+                    if ui.button("Resume").clicked() {
+                        slot.resume();
                     }
                 }
             }
+            if let Some(i) = remove {
+                let name = self.controller.slots()[i].name.clone();
+                self.egui_fields.computer_scopes.remove(&name);
+                self.controller.remove_computer(i);
+            }
+            if let Some((from, to)) = reorder {
+                self.controller.move_computer(from, to);
+            }
         });
 
-
         drop(game);
+        #[cfg(not(target_arch = "wasm32"))]
+        self.central_hub_window();
+        if let Some((server_addr, join)) = connect_to {
+            self.connect(server_addr, join);
+        }
     }
 
     fn user(&self) -> User {
@@ -938,6 +1460,29 @@ impl SpacecraftApp {
 }
 
 
+/// A small line graph of the last minute of a network statistic.
+fn stats_graph(ui: &mut egui::Ui, label: &str, values: &std::collections::VecDeque<f32>) {
+    ui.label(format!("{}: {:.0}", label, values.back().copied().unwrap_or(0.)));
+    let values = values.iter().copied().collect::<Vec<_>>();
+    egui::plot::Plot::new(label)
+        .height(40.)
+        .show_axes([false, true])
+        .allow_drag(false)
+        .allow_zoom(false)
+        .allow_scroll(false)
+        .include_y(0.)
+        .show(ui, |plot_ui| {
+            plot_ui.line(egui::plot::Line::new(egui::plot::PlotPoints::from_ys_f32(&values)));
+        });
+}
+
+fn start_recording(network_connection: &mut NetworkConnection, path: &Path) -> Result<(), String> {
+    network_connection
+        .record(path)
+        .map_err(|e| format!("Failed to start recording to {:?} ({})!", path, e))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
 fn block_on<F: Future>(rt: &Runtime, f: F) -> <F>::Output {
     rt.block_on(f)
 }
\ No newline at end of file
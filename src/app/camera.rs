@@ -1,5 +1,25 @@
 use super::*;
 
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CameraConfig {
+    /// Zoom is the base 2 logarithm of the scale, so the closest zoom is the largest one.
+    pub min_zoom: f32,
+    pub max_zoom: f32,
+    /// Zoom change per scrolled line.
+    pub scroll_speed: f32,
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        Self {
+            min_zoom: -15.,
+            max_zoom: -4.,
+            scroll_speed: 0.1,
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct Camera {
     pub center: Vec2,
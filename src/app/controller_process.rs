@@ -0,0 +1,267 @@
+//! Runs a computer library in a separate worker process, so a crash inside the
+//! computer can't take the client down with it.
+//!
+//! The worker is the `computer-worker` binary, started as `computer-worker <PATH> <ADDR>`.
+//! It is looked up in `COMPUTER_WORKER_ENV`, then next to the running executable, so any host
+//! embedding the library finds it as long as it is installed alongside. It connects back over a
+//! loopback TCP socket (stdout is left to the computer's own prints) and talks in
+//! length-prefixed frames of at most `MAX_FRAME_LEN` bytes: first the token the client passed
+//! in `WORKER_TOKEN_ENV`, then a `Result<(), String>` hello telling whether the library loaded,
+//! then one `Vec<GameCmd>` reply for every `(Game, User, (delayed, dropped))` request, the last
+//! part being the `SendFeedback` to pass on to the computer.
+//!
+//! Any local process can connect to the socket, so connections that don't open with the token
+//! are dropped, otherwise they would be sent the game and could play in the player's name.
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+
+use super::controller_select::{ComputerContainer, ComputerError};
+use super::*;
+use crate::network::SendFeedback;
+
+const WORKER_START_TIMEOUT: Duration = Duration::from_secs(10);
+/// Path of the worker binary, when it isn't installed next to the executable.
+pub const COMPUTER_WORKER_ENV: &str = "STELLAR_BIT_COMPUTER_WORKER";
+const WORKER_NAME: &str = "computer-worker";
+/// Secret the worker proves it was started by this client with, see the module docs.
+const WORKER_TOKEN_ENV: &str = "STELLAR_BIT_WORKER_TOKEN";
+/// Larger frames are rejected instead of allocated, whoever sent them.
+const MAX_FRAME_LEN: usize = 64 << 20;
+
+pub struct ComputerProcess {
+    child: Child,
+    stream: TcpStream,
+    responses: mpsc::Receiver<io::Result<Vec<u8>>>,
+}
+
+impl ComputerProcess {
+    pub fn spawn(path: &Path) -> Result<Self, ComputerError> {
+        let listener = TcpListener::bind("127.0.0.1:0").map_err(worker_error)?;
+        let addr = listener.local_addr().map_err(worker_error)?;
+        let token = format!("{:032x}", rand::random::<u128>());
+
+        let mut child = Command::new(worker_exe()?)
+            .arg(path)
+            .arg(addr.to_string())
+            .env(WORKER_TOKEN_ENV, &token)
+            .stdin(Stdio::null())
+            .spawn()
+            .map_err(worker_error)?;
+
+        let stream = match accept_worker(&listener, &mut child, &token) {
+            Ok(stream) => stream,
+            Err(e) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(e);
+            }
+        };
+
+        let (response_sender, responses) = mpsc::channel();
+        let process = Self {
+            stream,
+            child,
+            responses,
+        };
+        process.stream.set_nodelay(true).map_err(worker_error)?;
+
+        let mut reader = process.stream.try_clone().map_err(worker_error)?;
+        std::thread::spawn(move || loop {
+            let frame = read_frame(&mut reader, MAX_FRAME_LEN);
+            let failed = frame.is_err();
+            if response_sender.send(frame).is_err() || failed {
+                return;
+            }
+        });
+
+        let hello = match process.responses.recv_timeout(WORKER_START_TIMEOUT) {
+            Ok(hello) => hello.map_err(worker_error)?,
+            Err(_) => return Err(ComputerError::Worker("worker didn't load the computer in time".into())),
+        };
+        let hello: Result<(), String> = deserialize_bytes(&hello)
+            .map_err(|_| ComputerError::Worker("malformed hello from worker".into()))?;
+        hello.map_err(ComputerError::Worker)?;
+
+        Ok(process)
+    }
+
+    /// Sends the game to the worker and waits at most `timeout` for its commands.
+    /// After a timeout the worker is still busy, so it has to be dropped.
+    pub fn execute(
+        &mut self,
+        game: &Game,
+        user: &User,
+        feedback: SendFeedback,
+        timeout: Duration,
+    ) -> Result<Vec<GameCmd>, ComputerError> {
+        let request = serialize_bytes(&(game, user, (feedback.delayed, feedback.dropped)))
+            .map_err(|e| ComputerError::Worker(format!("couldn't encode the game: {:?}", e)))?;
+        write_frame(&mut self.stream, &request).map_err(worker_error)?;
+
+        let response = match self.responses.recv_timeout(timeout) {
+            Ok(response) => response.map_err(worker_error)?,
+            Err(mpsc::RecvTimeoutError::Timeout) => return Err(ComputerError::Timeout(timeout)),
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                return Err(ComputerError::Worker("worker stopped responding".into()));
+            }
+        };
+        deserialize_bytes(&response)
+            .map_err(|_| ComputerError::Worker("malformed commands from worker".into()))
+    }
+}
+
+impl Drop for ComputerProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Finds the worker binary, see the module docs.
+fn worker_exe() -> Result<PathBuf, ComputerError> {
+    if let Some(path) = std::env::var_os(COMPUTER_WORKER_ENV) {
+        let path = PathBuf::from(path);
+        return match path.is_file() {
+            true => Ok(path),
+            false => Err(ComputerError::Worker(format!(
+                "{} points at {:?}, which doesn't exist",
+                COMPUTER_WORKER_ENV, path
+            ))),
+        };
+    }
+    let exe = std::env::current_exe().map_err(worker_error)?;
+    let name = format!("{}{}", WORKER_NAME, std::env::consts::EXE_SUFFIX);
+    // test binaries live in `deps/`, one level below the other binaries
+    exe.ancestors()
+        .skip(1)
+        .take(2)
+        .map(|dir| dir.join(&name))
+        .find(|path| path.is_file())
+        .ok_or_else(|| {
+            ComputerError::Worker(format!(
+                "no {} next to {:?}, install it alongside or set {}",
+                name, exe, COMPUTER_WORKER_ENV
+            ))
+        })
+}
+
+/// Waits for the worker to connect, dropping every connection that doesn't open with `token`.
+fn accept_worker(
+    listener: &TcpListener,
+    child: &mut Child,
+    token: &str,
+) -> Result<TcpStream, ComputerError> {
+    listener.set_nonblocking(true).map_err(worker_error)?;
+    let started = std::time::Instant::now();
+    loop {
+        match listener.accept() {
+            Ok((mut stream, _)) => {
+                stream.set_nonblocking(false).map_err(worker_error)?;
+                // the worker sends the token right after connecting, a silent peer is dropped
+                stream
+                    .set_read_timeout(Some(Duration::from_secs(1)))
+                    .map_err(worker_error)?;
+                let proof = read_frame(&mut stream, token.len());
+                if proof.is_ok_and(|proof| proof == token.as_bytes()) {
+                    stream.set_read_timeout(None).map_err(worker_error)?;
+                    return Ok(stream);
+                }
+                log::warn!("Dropped a connection to the computer worker socket without the token");
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                if let Ok(Some(status)) = child.try_wait() {
+                    return Err(ComputerError::Worker(format!("worker exited early ({})", status)));
+                }
+                if started.elapsed() > WORKER_START_TIMEOUT {
+                    return Err(ComputerError::Worker("worker didn't connect in time".into()));
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            Err(e) => return Err(worker_error(e)),
+        }
+    }
+}
+
+fn worker_error(e: io::Error) -> ComputerError {
+    ComputerError::Worker(e.to_string())
+}
+
+fn write_frame(writer: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_le_bytes())?;
+    writer.write_all(data)?;
+    writer.flush()
+}
+
+/// Reads one frame, failing with `InvalidData` when it is longer than `max_len`.
+fn read_frame(reader: &mut impl Read, max_len: usize) -> io::Result<Vec<u8>> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > max_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame of {} bytes, at most {} are allowed", len, max_len),
+        ));
+    }
+    let mut data = vec![0; len];
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
+
+/// Entry point of the worker process. Returns the process exit code.
+pub fn run_worker(computer_path: PathBuf, addr: &str) -> i32 {
+    let Ok(token) = std::env::var(WORKER_TOKEN_ENV) else {
+        eprintln!("Computer worker is started by the client, {} isn't set", WORKER_TOKEN_ENV);
+        return 1;
+    };
+    let Ok(mut stream) = TcpStream::connect(addr) else {
+        eprintln!("Computer worker couldn't connect to the client at {}", addr);
+        return 1;
+    };
+    let _ = stream.set_nodelay(true);
+    if write_frame(&mut stream, token.as_bytes()).is_err() {
+        return 1;
+    }
+
+    let computer = ComputerContainer::load(computer_path);
+    let hello = computer.as_ref().map(|_| ()).map_err(|e| e.to_string());
+    let Ok(hello) = serialize_bytes(&hello) else {
+        eprintln!("Computer worker couldn't encode its hello");
+        return 1;
+    };
+    if write_frame(&mut stream, &hello).is_err() {
+        return 1;
+    }
+    let Ok(computer) = computer else {
+        return 1;
+    };
+
+    let egui_context = egui::Context::default();
+    loop {
+        // The client closing the socket is the normal way for the worker to end.
+        let Ok(request) = read_frame(&mut stream, MAX_FRAME_LEN) else {
+            return 0;
+        };
+        let Ok((mut game, user, (delayed, dropped))) =
+            deserialize_bytes::<(Game, User, (u32, u32))>(&request)
+        else {
+            eprintln!("Computer worker received a malformed game");
+            return 1;
+        };
+        let feedback = SendFeedback { delayed, dropped };
+        if !feedback.is_empty() {
+            computer.feedback(feedback);
+        }
+        let cmds = computer.execute(&mut game, &user, &egui_context);
+        let Ok(cmds) = serialize_bytes(&cmds) else {
+            eprintln!("Computer worker couldn't encode the computer's commands");
+            return 1;
+        };
+        if write_frame(&mut stream, &cmds).is_err() {
+            return 0;
+        }
+    }
+}
@@ -1,11 +1,41 @@
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::SystemTime;
 
 use super::*;
-use dlopen::wrapper::{Container, WrapperApi};
-use dlopen_derive::WrapperApi;
+use super::controller_process::ComputerProcess;
+use super::controller_wasmtime::WasmComputer;
+use crate::network::{Backoff, FeedbackReport, SendFeedback};
+use log::error;
+use dlopen::wrapper::{Container, WrapperApi, WrapperMultiApi};
+use dlopen_derive::{WrapperApi, WrapperMultiApi};
+
+/// Hash identifying the build of the types shared with computers through raw pointers.
+///
+/// A computer exports it as `stellar_bit_layout_hash`. It covers the compiler version and the
+/// locked version and source (git revision included) of `stellar-bit-core` and `egui`, read
+/// from `Cargo.lock` by the build script. None of these types is `#[repr(C)]`, so anything
+/// less, like their sizes, can't tell two builds apart. Equal hashes still only mean the same
+/// sources and compiler, not a checked layout: a lockfile the build script can't find leaves
+/// only the compiler version in the hash.
+pub fn computer_layout_hash() -> u64 {
+    // FNV-1a, unlike `DefaultHasher` it is the same on every Rust version
+    env!("STELLAR_BIT_COMPUTER_BUILD")
+        .bytes()
+        .fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+}
 
 #[derive(WrapperApi)]
-struct Computer {
+struct ComputerAbi {
+    stellar_bit_abi_version: extern "C" fn() -> u32,
+    stellar_bit_layout_hash: extern "C" fn() -> u64,
+}
+
+#[derive(WrapperApi)]
+struct ComputerEntry {
     execute: extern "C" fn(
         pointers: *const (
             *mut Game,
@@ -16,30 +46,149 @@ struct Computer {
     ),
 }
 
-struct ComputerContainer {
-    pub computer: Container<Computer>,
-    pub path: PathBuf,
+#[derive(WrapperApi)]
+struct ComputerInit {
+    init: extern "C" fn(),
 }
 
-impl ComputerContainer {
-    pub fn load(path: PathBuf) -> Self {
-        let computer = unsafe { Container::<Computer>::load(&path).unwrap() };
-        Self { path, computer }
+#[derive(WrapperApi)]
+struct ComputerShutdown {
+    shutdown: extern "C" fn(),
+}
+
+#[derive(WrapperApi)]
+struct ComputerFeedback {
+    feedback: extern "C" fn(delayed: u32, dropped: u32),
+}
+
+#[derive(WrapperMultiApi)]
+struct Computer {
+    abi: Option<ComputerAbi>,
+    entry: ComputerEntry,
+    init: Option<ComputerInit>,
+    shutdown: Option<ComputerShutdown>,
+    feedback: Option<ComputerFeedback>,
+}
+
+#[derive(Debug)]
+pub enum ComputerError {
+    Copy(std::io::Error),
+    Library(dlopen::Error),
+    MissingAbi,
+    AbiMismatch { expected: u32, found: u32 },
+    LayoutMismatch { expected: u64, found: u64 },
+    Worker(String),
+    Timeout(Duration),
+    Wasm(String),
+    OutOfFuel,
+    NameTaken(String),
+}
+
+impl std::fmt::Display for ComputerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ComputerError::Copy(e) => write!(f, "couldn't copy computer library: {}", e),
+            ComputerError::Library(e) => write!(f, "couldn't load computer library: {}", e),
+            ComputerError::MissingAbi => write!(
+                f,
+                "computer doesn't export `stellar_bit_abi_version` and `stellar_bit_layout_hash`, \
+                 see the README for the symbols a computer has to export"
+            ),
+            ComputerError::AbiMismatch { expected, found } => write!(
+                f,
+                "computer uses ABI version {}, but this client supports version {}",
+                found, expected
+            ),
+            ComputerError::LayoutMismatch { expected, found } => write!(
+                f,
+                "computer was built against a different stellar-bit-core, egui or compiler \
+                 (layout hash {:016x}, expected {:016x})",
+                found, expected
+            ),
+            ComputerError::Worker(e) => write!(f, "computer worker failed: {}", e),
+            ComputerError::Timeout(timeout) => {
+                write!(f, "computer didn't answer within {:?}", timeout)
+            }
+            ComputerError::Wasm(e) => write!(f, "wasm computer failed: {}", e),
+            ComputerError::OutOfFuel => write!(f, "wasm computer ran out of fuel"),
+            ComputerError::NameTaken(name) => {
+                write!(f, "a computer called '{}' is already loaded", name)
+            }
+        }
     }
 }
 
-pub struct Controller {
-    computer_cont: Option<ComputerContainer>,
+/// A private copy of a computer library. dlopen hands back the already loaded library when
+/// the same path is opened twice, so every load goes through a fresh copy. This also keeps
+/// `cargo build` from overwriting a library that is in use.
+struct LibraryCopy {
+    path: PathBuf,
 }
 
-impl Controller {
-    pub fn new() -> Self {
-        Self {
-            computer_cont: None,
+impl LibraryCopy {
+    fn create(original: &Path) -> std::io::Result<Self> {
+        static COPIES: AtomicU32 = AtomicU32::new(0);
+        let extension = original.extension().and_then(|e| e.to_str()).unwrap_or("so");
+        let path = std::env::temp_dir().join(format!(
+            "stellar-bit-computer-{}-{}.{}",
+            std::process::id(),
+            COPIES.fetch_add(1, Ordering::Relaxed),
+            extension
+        ));
+        std::fs::copy(original, &path)?;
+        Ok(Self { path })
+    }
+}
+
+impl Drop for LibraryCopy {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+pub struct ComputerContainer {
+    computer: Container<Computer>,
+    pub path: PathBuf,
+    // declared after `computer`, so the file is removed only once the library is closed
+    _copy: LibraryCopy,
+}
+
+impl ComputerContainer {
+    /// Loads the library and checks it was built for this client before anything is called.
+    pub fn load(path: PathBuf) -> Result<Self, ComputerError> {
+        let copy = LibraryCopy::create(&path).map_err(ComputerError::Copy)?;
+        let computer =
+            unsafe { Container::<Computer>::load(&copy.path).map_err(ComputerError::Library)? };
+
+        let Some(abi) = &computer.abi else {
+            return Err(ComputerError::MissingAbi);
+        };
+        let abi_version = abi.stellar_bit_abi_version();
+        if abi_version != COMPUTER_ABI_VERSION {
+            return Err(ComputerError::AbiMismatch {
+                expected: COMPUTER_ABI_VERSION,
+                found: abi_version,
+            });
+        }
+        let layout_hash = abi.stellar_bit_layout_hash();
+        if layout_hash != computer_layout_hash() {
+            return Err(ComputerError::LayoutMismatch {
+                expected: computer_layout_hash(),
+                found: layout_hash,
+            });
+        }
+
+        if let Some(init) = &computer.init {
+            init.init();
         }
+        Ok(Self {
+            path,
+            computer,
+            _copy: copy,
+        })
     }
-    pub fn retrieve_cmds(
-        &mut self,
+    pub fn execute(
+        &self,
         game: &mut Game,
         user: &User,
         egui_context: &egui::Context,
@@ -56,16 +205,615 @@ impl Controller {
             *const egui::Context,
             *mut Vec<GameCmd>,
         ) = &(game_ptr, user_ptr, egui_ctx_ptr, network_game_cmds_ptr) as *const _;
-        if let Some(computer_cont) = &self.computer_cont {
-            computer_cont.computer.execute(pointers);
+        self.computer.entry.execute(pointers);
+
+        network_game_cmds
+    }
+    /// Tells the computer how many of its commands the rate limit delayed or dropped,
+    /// if it wants to know.
+    pub fn feedback(&self, feedback: SendFeedback) {
+        if let Some(computer_feedback) = &self.computer.feedback {
+            computer_feedback.feedback(feedback.delayed, feedback.dropped);
+        }
+    }
+}
+
+impl Drop for ComputerContainer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = &self.computer.shutdown {
+            shutdown.shutdown();
+        }
+    }
+}
+
+type LibraryRequest = (Game, User, egui::Context, SendFeedback);
+
+/// In-process computers can't be stopped, so there is a limit on how many hung threads
+/// are left behind before hanging computers aren't started in-process anymore.
+const MAX_ABANDONED_RUNNERS: u32 = 4;
+static ABANDONED_RUNNERS: AtomicU32 = AtomicU32::new(0);
+
+/// Threads of in-process computers that were abandoned in a hung call and haven't returned.
+pub fn abandoned_computer_threads() -> u32 {
+    ABANDONED_RUNNERS.load(Ordering::Relaxed)
+}
+
+/// Runs an in-process computer on a thread of its own, so a call that hangs is abandoned
+/// instead of freezing the client. The thread works on its own copy of the game, nothing it
+/// still does after being abandoned can touch the client's.
+struct LibraryRunner {
+    requests: mpsc::Sender<LibraryRequest>,
+    responses: mpsc::Receiver<(Vec<GameCmd>, Game)>,
+    abandoned: Arc<AtomicBool>,
+}
+
+impl LibraryRunner {
+    fn spawn(computer: ComputerContainer) -> Result<Self, ComputerError> {
+        let abandoned_runners = abandoned_computer_threads();
+        if abandoned_runners >= MAX_ABANDONED_RUNNERS {
+            return Err(ComputerError::Worker(format!(
+                "{} computer threads are hung already, run computers that hang with --isolated",
+                abandoned_runners
+            )));
         }
+        let (request_sender, requests) = mpsc::channel::<LibraryRequest>();
+        let (response_sender, responses) = mpsc::channel();
+        let abandoned = Arc::new(AtomicBool::new(false));
+        let thread_abandoned = abandoned.clone();
+        std::thread::Builder::new()
+            .name(format!("computer {:?}", computer.path))
+            .spawn(move || {
+                // ends once the runner is dropped, unloading the library on this thread
+                for (mut game, user, egui_context, feedback) in requests {
+                    if !feedback.is_empty() {
+                        computer.feedback(feedback);
+                    }
+                    let cmds = computer.execute(&mut game, &user, &egui_context);
+                    if response_sender.send((cmds, game)).is_err() {
+                        break;
+                    }
+                }
+                if thread_abandoned.load(Ordering::Relaxed) {
+                    ABANDONED_RUNNERS.fetch_sub(1, Ordering::Relaxed);
+                }
+            })
+            .map_err(|e| ComputerError::Worker(e.to_string()))?;
+        Ok(Self {
+            requests: request_sender,
+            responses,
+            abandoned,
+        })
+    }
 
+    /// Runs the computer on `game` and hands it back with the commands, waiting at most
+    /// `timeout`.
+    ///
+    /// After a timeout the thread is still busy in the computer, so the runner has to be
+    /// dropped. The thread is left behind with the game and `egui_context` until the call
+    /// returns, if ever, and the library stays loaded until then. Every such thread counts
+    /// towards `MAX_ABANDONED_RUNNERS`.
+    fn execute(
+        &mut self,
+        game: Game,
+        user: &User,
+        egui_context: &egui::Context,
+        feedback: SendFeedback,
+        timeout: Duration,
+    ) -> Result<(Vec<GameCmd>, Game), ComputerError> {
+        self.requests
+            .send((game, *user, egui_context.clone(), feedback))
+            .map_err(|_| ComputerError::Worker("computer thread stopped".into()))?;
+        match self.responses.recv_timeout(timeout) {
+            Ok(response) => Ok(response),
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                self.abandoned.store(true, Ordering::Relaxed);
+                let abandoned = ABANDONED_RUNNERS.fetch_add(1, Ordering::Relaxed) + 1;
+                warn!(
+                    "Abandoned a hung computer thread, {} of at most {} are left behind",
+                    abandoned, MAX_ABANDONED_RUNNERS
+                );
+                Err(ComputerError::Timeout(timeout))
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                Err(ComputerError::Worker("computer thread stopped".into()))
+            }
+        }
+    }
+}
+
+/// Limits on how long a computer may take per call of `execute`.
+#[derive(Clone, Debug)]
+pub struct ExecutionBudget {
+    pub limit: Duration,
+    /// Consecutive calls over `limit` after which the computer gets suspended.
+    pub max_overruns: u32,
+    /// A computer that doesn't answer in this time is restarted. Its worker process is
+    /// killed, the thread of an in-process computer is abandoned and keeps running until
+    /// the call returns, if ever. Once `MAX_ABANDONED_RUNNERS` threads hang, computers
+    /// only restart with `--isolated`.
+    pub call_timeout: Duration,
+    /// Fuel a wasm computer gets per call, roughly the number of instructions it may run.
+    pub wasm_fuel: u64,
+    /// Memory a wasm computer may grow to, in bytes.
+    pub wasm_memory: usize,
+}
+
+impl Default for ExecutionBudget {
+    fn default() -> Self {
+        Self {
+            limit: Duration::from_millis(20),
+            max_overruns: 10,
+            call_timeout: Duration::from_secs(1),
+            wasm_fuel: 500_000_000,
+            wasm_memory: 256 << 20,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ExecutionStats {
+    pub last: Duration,
+    pub max: Duration,
+    pub overruns: u32,
+    pub consecutive_overruns: u32,
+    pub suspended: bool,
+}
+
+enum ComputerBackend {
+    /// The library is loaded into the client process, and runs on a thread of its own.
+    Library(LibraryRunner),
+    /// The library runs in a worker process, see `controller_process`.
+    Process(ComputerProcess),
+    /// A wasm module in the embedded runtime, see `controller_wasmtime`.
+    Wasm(WasmComputer),
+}
+
+impl ComputerBackend {
+    /// `.wasm` files always run in the embedded runtime, they are sandboxed already.
+    fn load(
+        computer_path: &Path,
+        isolated: bool,
+        budget: &ExecutionBudget,
+    ) -> Result<Self, ComputerError> {
+            if computer_path.extension().is_some_and(|extension| extension == "wasm") {
+            return Ok(ComputerBackend::Wasm(WasmComputer::load(computer_path, budget)?));
+        } else if isolated {
+            return Ok(ComputerBackend::Process(ComputerProcess::spawn(computer_path)?));
+        }
+        let computer = ComputerContainer::load(computer_path.into())?;
+        Ok(ComputerBackend::Library(LibraryRunner::spawn(computer)?))
+    }
+}
+
+/// A computer to load into the `Controller`.
+#[derive(Clone, Debug)]
+pub struct ComputerSpec {
+    pub name: String,
+    pub path: PathBuf,
+    /// Limits the computer to the player's spacecraft carrying at least one of these tags.
+    pub scope: Option<Vec<String>>,
+}
+
+impl ComputerSpec {
+    /// Names the computer after its file, so `libmining.so` becomes `mining`.
+    pub fn new(path: PathBuf) -> Self {
+        let name = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .map(|stem| stem.strip_prefix("lib").unwrap_or(stem))
+            .unwrap_or("computer")
+            .to_owned();
+        Self {
+            name,
+            path,
+            scope: None,
+        }
+    }
+}
+
+/// A loaded computer together with its own stats, restarts and reloads.
+pub struct ComputerSlot {
+    pub name: String,
+    pub enabled: bool,
+    /// The player's spacecraft without any of these tags are hidden from the computer.
+    /// Everything else in the game stays visible to it. Commands that fail on the scoped
+    /// game, e.g. because they are for a hidden spacecraft, are dropped.
+    pub scope: Option<Vec<String>>,
+    path: PathBuf,
+    backend: Option<ComputerBackend>,
+    restart_backoff: Backoff,
+    restart_at: Option<Duration>,
+    pub restarts: u32,
+    stats: ExecutionStats,
+    loaded_modified: Option<SystemTime>,
+    pending_modified: Option<SystemTime>,
+    reloads: u32,
+    pub reload_status: Option<String>,
+    /// Not yet delivered to the computer, it is told right before its next execution.
+    feedback: SendFeedback,
+    /// Replaces the client's egui context once a call timed out, so a computer that hangs
+    /// once can't be left behind with it again.
+    own_egui_context: Option<egui::Context>,
+}
+
+impl ComputerSlot {
+    fn load(
+        spec: ComputerSpec,
+        isolated: bool,
+        budget: &ExecutionBudget,
+    ) -> Result<Self, ComputerError> {
+        let backend = ComputerBackend::load(&spec.path, isolated, budget)?;
+        Ok(Self {
+            name: spec.name,
+            enabled: true,
+            scope: spec.scope,
+            loaded_modified: modified_time(&spec.path),
+            path: spec.path,
+            backend: Some(backend),
+            restart_backoff: Backoff::default(),
+            restart_at: None,
+            restarts: 0,
+            stats: ExecutionStats::default(),
+            pending_modified: None,
+            reloads: 0,
+            reload_status: None,
+            feedback: SendFeedback::default(),
+            own_egui_context: None,
+        })
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    pub fn stats(&self) -> &ExecutionStats {
+        &self.stats
+    }
+    /// Lets a suspended computer run again.
+    pub fn resume(&mut self) {
+        self.stats.suspended = false;
+        self.stats.consecutive_overruns = 0;
+    }
+    /// `copy` is the copy of `game` in-process computers run on, shared by all slots of a
+    /// tick. It is made when missing, and the commands are applied to both.
+    fn execute(
+        &mut self,
+        game: &mut Game,
+        copy: &mut Option<Game>,
+        user: &User,
+        egui_context: &egui::Context,
+        budget: &ExecutionBudget,
+    ) -> Vec<GameCmd> {
+        if !self.enabled || self.stats.suspended {
+            return vec![];
+        }
+        let cmds = match self.scope.as_ref().map(|tags| scoped_game(game, user, tags)) {
+            None => self.run(game, copy, user, egui_context, budget),
+            Some(mut scoped_game) => {
+                let mut scoped_copy = None;
+                let cmds = self.run(&scoped_game, &mut scoped_copy, user, egui_context, budget);
+                cmds.into_iter()
+                    .filter(|cmd| match scoped_game.execute_cmd(*user, cmd.clone()) {
+                        Ok(_) => true,
+                        Err(err) => {
+                            warn!("Dropped command of computer '{}' outside its scope: {:?}", self.name, err);
+                            false
+                        }
+                    })
+                    .collect()
+            }
+        };
+        apply_cmds(game, user, &cmds);
+        if let Some(copy) = copy {
+            apply_cmds(copy, user, &cmds);
+        }
+        cmds
+    }
+    /// Runs the computer without applying its commands. An in-process computer runs on
+    /// `copy`, which is taken while it runs and lost if it hangs.
+    fn run(
+        &mut self,
+        game: &Game,
+        copy: &mut Option<Game>,
+        user: &User,
+        egui_context: &egui::Context,
+        budget: &ExecutionBudget,
+    ) -> Vec<GameCmd> {
+        let feedback = std::mem::take(&mut self.feedback);
+        let start = std::time::Instant::now();
+        let result = match &mut self.backend {
+            Some(ComputerBackend::Library(runner)) => {
+                let game = copy.take().unwrap_or_else(|| game.clone());
+                let egui_context = self.own_egui_context.as_ref().unwrap_or(egui_context);
+                runner
+                    .execute(game, user, egui_context, feedback, budget.call_timeout)
+                    .map(|(cmds, game)| {
+                        *copy = Some(game);
+                        cmds
+                    })
+            }
+                    Some(ComputerBackend::Process(process)) => {
+                process.execute(game, user, feedback, budget.call_timeout)
+            }
+                    Some(ComputerBackend::Wasm(computer)) => computer.execute(game, user, feedback, budget),
+            None => {
+                self.feedback = feedback;
+                return vec![];
+            }
+        };
+        let cmds = match result {
+            Ok(cmds) => {
+                self.restart_backoff.reset();
+                cmds
+            }
+            Err(e) => {
+                if matches!(e, ComputerError::Timeout(_)) {
+                    self.own_egui_context.get_or_insert_with(egui::Context::default);
+                }
+                warn!("Computer '{}' failed, restarting it: {}", self.name, e);
+                self.backend = None;
+                self.schedule_restart();
+                vec![]
+            }
+        };
+        self.record_execution(start.elapsed(), budget);
+
+        cmds
+    }
+    fn record_execution(&mut self, elapsed: Duration, budget: &ExecutionBudget) {
+        let stats = &mut self.stats;
+        stats.last = elapsed;
+        stats.max = stats.max.max(elapsed);
+        if elapsed <= budget.limit {
+            stats.consecutive_overruns = 0;
+            return;
+        }
+        if stats.consecutive_overruns == 0 {
+            warn!(
+                "Computer '{}' took {:?}, over its budget of {:?}",
+                self.name, elapsed, budget.limit
+            );
+        }
+        stats.overruns += 1;
+        stats.consecutive_overruns += 1;
+        if stats.consecutive_overruns >= budget.max_overruns {
+            error!(
+                "Computer '{}' exceeded its budget of {:?} {} times in a row, suspending it",
+                self.name, budget.limit, stats.consecutive_overruns
+            );
+            stats.suspended = true;
+        }
+    }
+    fn reload(&mut self, isolated: bool, budget: &ExecutionBudget) -> Result<(), ComputerError> {
+        self.backend = None;
+        self.restart_at = None;
+        self.backend = Some(ComputerBackend::load(&self.path, isolated, budget)?);
+        self.stats = ExecutionStats::default();
+        Ok(())
+    }
+    /// Swaps in the new version of the library once its file has stopped changing.
+    /// If the new version fails to load, the old one keeps running.
+    fn poll_hot_reload(&mut self, isolated: bool, budget: &ExecutionBudget) {
+        let modified = modified_time(&self.path);
+        if modified.is_none() || modified == self.loaded_modified {
+            self.pending_modified = None;
+            return;
+        }
+        // the file is probably still being written, wait for the next check
+        if modified != self.pending_modified {
+            self.pending_modified = modified;
+            return;
+        }
+        self.pending_modified = None;
+        self.loaded_modified = modified;
+
+        match ComputerBackend::load(&self.path, isolated, budget) {
+            Ok(backend) => {
+                self.backend = Some(backend);
+                self.restart_at = None;
+                self.stats = ExecutionStats::default();
+                self.reloads += 1;
+                println!("Reloaded computer '{}' from {:?}", self.name, self.path);
+                self.reload_status = Some(format!("Reloaded {} times", self.reloads));
+            }
+            Err(e) => {
+                warn!(
+                    "Reloading computer '{}' from {:?} failed, keeping the previous one: {}",
+                    self.name, self.path, e
+                );
+                self.reload_status = Some(format!("Reload failed, keeping the previous one: {}", e));
+            }
+        }
+    }
+    fn schedule_restart(&mut self) {
+        self.restart_at = self
+            .restart_backoff
+            .next_delay()
+            .map(|(_, delay)| now() + delay);
+        if self.restart_at.is_none() {
+            error!("Computer '{}' keeps crashing, giving up", self.name);
+        }
+    }
+    fn poll_restart(&mut self, isolated: bool, budget: &ExecutionBudget) {
+        let Some(restart_at) = self.restart_at else {
+            return;
+        };
+        if now() < restart_at {
+            return;
+        }
+        self.restart_at = None;
+        match ComputerBackend::load(&self.path, isolated, budget) {
+            Ok(backend) => {
+                self.restarts += 1;
+                self.backend = Some(backend);
+            }
+            Err(e) => {
+                warn!("Failed to restart computer '{}': {}", self.name, e);
+                self.schedule_restart();
+            }
+        }
+    }
+}
+
+/// Runs any number of computers side by side.
+///
+/// Computers run one after another in the order of their slots, each seeing the effects of
+/// the ones before it, and their commands are returned in that same order. In-process
+/// computers share one copy of the game per tick, so they also see what the ones before
+/// them changed in it directly.
+pub struct Controller {
+    slots: Vec<ComputerSlot>,
+    isolated: bool,
+    pub budget: ExecutionBudget,
+    /// Reload a computer whenever its library file changes.
+    pub hot_reload: bool,
+    watch_interval: Interval,
+}
+
+impl Controller {
+    pub fn new() -> Self {
+        Self {
+            slots: vec![],
+            isolated: false,
+            budget: ExecutionBudget::default(),
+            hot_reload: true,
+            watch_interval: Interval::new(Duration::from_millis(500)),
+        }
+    }
+    pub fn retrieve_cmds(
+        &mut self,
+        game: &mut Game,
+        user: &User,
+        egui_context: &egui::Context,
+    ) -> Vec<GameCmd> {
+        self.retrieve_cmds_by_computer(game, user, egui_context)
+            .into_iter()
+            .flat_map(|(_, cmds)| cmds)
+            .collect()
+    }
+    /// Like `retrieve_cmds`, with the commands of every computer kept apart under its
+    /// name, e.g. to send them with `NetworkConnection::send_cmds`.
+    pub fn retrieve_cmds_by_computer(
+        &mut self,
+        game: &mut Game,
+        user: &User,
+        egui_context: &egui::Context,
+    ) -> Vec<(String, Vec<GameCmd>)> {
+        let check_files = self.hot_reload && self.watch_interval.check();
+        let mut network_game_cmds = vec![];
+        // in-process computers take turns on one copy of the game
+        let mut copy = None;
+        for slot in &mut self.slots {
+            slot.poll_restart(self.isolated, &self.budget);
+            if check_files {
+                slot.poll_hot_reload(self.isolated, &self.budget);
+            }
+            let cmds = slot.execute(game, &mut copy, user, egui_context, &self.budget);
+            if !cmds.is_empty() {
+                network_game_cmds.push((slot.name.clone(), cmds));
+            }
+        }
         network_game_cmds
     }
-    pub fn select_computer(&mut self, computer_path: PathBuf) {
-        self.computer_cont = Some(ComputerContainer::load(computer_path));
+    /// Passes on what the rate limit did to the commands, every computer gets told about
+    /// its own commands before its next execution.
+    pub fn report_feedback(&mut self, report: FeedbackReport) {
+        for slot in &mut self.slots {
+            if let Some(feedback) = report.by_origin.get(&slot.name) {
+                slot.feedback.add(*feedback);
+            }
+        }
+    }
+    pub fn slots(&self) -> &[ComputerSlot] {
+        &self.slots
+    }
+    pub fn slots_mut(&mut self) -> &mut [ComputerSlot] {
+        &mut self.slots
     }
-    pub fn computer_path(&self) -> Option<PathBuf> {
-        self.computer_cont.as_ref().map(|cc| cc.path.clone())
+    /// Loads a computer into a new slot after the existing ones.
+    pub fn add_computer(&mut self, spec: ComputerSpec) -> Result<(), ComputerError> {
+        if self.slots.iter().any(|slot| slot.name == spec.name) {
+            return Err(ComputerError::NameTaken(spec.name));
+        }
+        self.slots.push(ComputerSlot::load(spec, self.isolated, &self.budget)?);
+        Ok(())
+    }
+    /// Returns `name`, or `name` with a number appended if a computer is already called that.
+    pub fn unique_name(&self, name: &str) -> String {
+        let taken = |name: &str| self.slots.iter().any(|slot| slot.name == name);
+        if !taken(name) {
+            return name.to_owned();
+        }
+        (2..)
+            .map(|n| format!("{}-{}", name, n))
+            .find(|name| !taken(name))
+            .unwrap()
+    }
+    /// Unloads the computer in the given slot.
+    pub fn remove_computer(&mut self, index: usize) {
+        if index < self.slots.len() {
+            self.slots.remove(index);
+        }
+    }
+    /// Moves a computer to another position in the execution order.
+    pub fn move_computer(&mut self, index: usize, new_index: usize) {
+        if index >= self.slots.len() {
+            return;
+        }
+        let slot = self.slots.remove(index);
+        self.slots.insert(new_index.min(self.slots.len()), slot);
+    }
+    /// Switches between running computers in-process and in worker processes,
+    /// reloading every loaded computer in the new mode.
+    pub fn set_isolated(&mut self, isolated: bool) -> Result<(), ComputerError> {
+        if self.isolated == isolated {
+            return Ok(());
+        }
+        self.isolated = isolated;
+        let mut result = Ok(());
+        for slot in &mut self.slots {
+            if slot.path.extension().is_some_and(|extension| extension == "wasm") {
+                continue;
+            }
+            if let Err(e) = slot.reload(isolated, &self.budget) {
+                error!("Failed to reload computer '{}': {}", slot.name, e);
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }
+    pub fn isolated(&self) -> bool {
+        self.isolated
     }
+    /// Drops all computers, closing their libraries and stopping their workers.
+    pub fn unload(&mut self) {
+        self.slots.clear();
+    }
+}
+
+/// A copy of the game in which the player's spacecraft outside of `tags` don't exist.
+fn scoped_game(game: &Game, user: &User, tags: &[String]) -> Game {
+    let mut game = game.clone();
+    if let User::Player(player_id) = user {
+        game.game_objects.retain(|_, game_object| match &*game_object {
+            GameObject::Spacecraft(spacecraft) if game_object.owner() == Some(*player_id) => {
+                spacecraft.tags.iter().any(|tag| tags.contains(tag))
+            }
+            _ => true,
+        });
+    }
+    game
+}
+
+/// Applies the commands of a computer, which ran on a copy of the game.
+fn apply_cmds(game: &mut Game, user: &User, cmds: &[GameCmd]) {
+    for cmd in cmds.iter().cloned() {
+        if let Err(err) = game.execute_cmd(*user, cmd) {
+            warn!("Error executing command from computer: {:?}", err);
+        }
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
 }
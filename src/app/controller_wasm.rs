@@ -1,9 +1,9 @@
 use super::*;
 use js_sys::{Function, Object, Reflect, WebAssembly};
-use log::warn;
+use log::{error, warn};
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
-use wasm_bindgen_futures::{spawn_local, JsFuture};
+use wasm_bindgen_futures::JsFuture;
 
 pub struct Controller {
     network_game_cmds: Vec<GameCmd>,
@@ -17,16 +17,161 @@ impl Controller {
     }
 }
 
+/// The origin the commands of the web computer are sent with.
+const COMPUTER_NAME: &str = "computer";
+
 pub static mut EXECUTION_FUNCTION: Option<Function> = None;
+pub static mut FEEDBACK_FUNCTION: Option<Function> = None;
+/// Set by `load_computer_module`, used instead of the functions above.
+static mut COMPUTER_MODULE: Option<ComputerModule> = None;
+
+/// A computer built for the wasm ABI described in the README ("Wasm computers"), the
+/// same module the native client runs with wasmtime.
+struct ComputerModule {
+    memory: WebAssembly::Memory,
+    alloc: Function,
+    execute: Function,
+    feedback: Option<Function>,
+}
+
+impl ComputerModule {
+    async fn instantiate(bytes: &[u8]) -> Result<Self, JsValue> {
+        let result = JsFuture::from(WebAssembly::instantiate_buffer(bytes, &Object::new())).await?;
+        let instance: WebAssembly::Instance = Reflect::get(&result, &"instance".into())?.dyn_into()?;
+        let exports = instance.exports();
+        let export = |name: &str| -> Result<JsValue, JsValue> {
+            let value = Reflect::get(&exports, &name.into())?;
+            if value.is_undefined() {
+                return Err(format!("module doesn't export `{}`", name).into());
+            }
+            Ok(value)
+        };
+
+        let abi_version = export("stellar_bit_abi_version")?
+            .dyn_into::<Function>()?
+            .call0(&JsValue::NULL)?
+            .as_f64()
+            .unwrap_or(-1.);
+        if abi_version != COMPUTER_ABI_VERSION as f64 {
+            return Err(format!(
+                "computer ABI version {} doesn't match the client's {}",
+                abi_version, COMPUTER_ABI_VERSION
+            )
+            .into());
+        }
+
+        Ok(Self {
+            memory: export("memory")?.dyn_into()?,
+            alloc: export("alloc")?.dyn_into()?,
+            execute: export("execute")?.dyn_into()?,
+            feedback: export("feedback").ok().map(|f| f.dyn_into()).transpose()?,
+        })
+    }
+
+    fn execute(&self, game: &Game, user: &User) -> Result<Vec<GameCmd>, JsValue> {
+        let game = serialize_bytes(game).map_err(|_| "couldn't serialize the game")?;
+        let user = serialize_bytes(user).map_err(|_| "couldn't serialize the user")?;
+        let (game_ptr, game_len) = self.write(&game)?;
+        let (user_ptr, user_len) = self.write(&user)?;
+        let args = js_sys::Array::of4(
+            &game_ptr.into(),
+            &game_len.into(),
+            &user_ptr.into(),
+            &user_len.into(),
+        );
+        let packed = u64::try_from(self.execute.apply(&JsValue::NULL, &args)?)?;
+
+        let (cmds_ptr, cmds_len) = ((packed >> 32) as u32, packed as u32);
+        let cmds = self.bytes().subarray(cmds_ptr, cmds_ptr + cmds_len).to_vec();
+        Ok(deserialize_bytes(&cmds).map_err(|_| "malformed commands")?)
+    }
+
+    fn write(&self, data: &[u8]) -> Result<(u32, u32), JsValue> {
+        let len = data.len() as u32;
+        let ptr = self
+            .alloc
+            .call1(&JsValue::NULL, &len.into())?
+            .as_f64()
+            .ok_or("`alloc` didn't return a pointer")? as u32;
+        // the buffer is replaced whenever the memory grows, so it's looked up after `alloc`
+        self.bytes().set(&js_sys::Uint8Array::from(data), ptr);
+        Ok((ptr, len))
+    }
+
+    fn bytes(&self) -> js_sys::Uint8Array {
+        js_sys::Uint8Array::new(&self.memory.buffer())
+    }
+}
 
 impl Controller {
+    /// Calls the feedback function, if one was set, with the number of delayed and
+    /// dropped commands. There is only the one computer, all commands are its own.
+    pub fn report_feedback(&mut self, report: crate::network::FeedbackReport) {
+        let feedback = report.total;
+        if feedback.is_empty() {
+            return;
+        }
+        if let Some(module) = unsafe { &*&raw const COMPUTER_MODULE } {
+            let Some(feedback_func) = &module.feedback else {
+                return;
+            };
+            if let Err(e) = feedback_func.call2(
+                &JsValue::NULL,
+                &JsValue::from(feedback.delayed),
+                &JsValue::from(feedback.dropped),
+            ) {
+                warn!("Computer module feedback failed: {:?}", e);
+            }
+            return;
+        }
+        let Some(feedback_func) = (unsafe { &*&raw const FEEDBACK_FUNCTION }) else {
+            return;
+        };
+        if let Err(e) = feedback_func.call2(
+            &JsValue::NULL,
+            &JsValue::from(feedback.delayed),
+            &JsValue::from(feedback.dropped),
+        ) {
+            warn!("Computer feedback function failed: {:?}", e);
+        }
+    }
+
+    pub fn retrieve_cmds_by_computer(
+        &mut self,
+        game: &mut Game,
+        user: &User,
+        egui_context: &egui::Context,
+    ) -> Vec<(String, Vec<GameCmd>)> {
+        let cmds = self.retrieve_cmds(game, user, egui_context);
+        if cmds.is_empty() {
+            return vec![];
+        }
+        vec![(COMPUTER_NAME.into(), cmds)]
+    }
+
     pub fn retrieve_cmds(
         &mut self,
         game: &mut Game,
         user: &User,
         _egui_context: &egui::Context,
     ) -> Vec<GameCmd> {
-        let Some(execution_func) = (unsafe { &EXECUTION_FUNCTION }) else {
+        if let Some(module) = unsafe { &*&raw const COMPUTER_MODULE } {
+            let cmds = match module.execute(game, user) {
+                Ok(cmds) => cmds,
+                Err(e) => {
+                    error!("Computer module failed: {:?}", e);
+                    return vec![];
+                }
+            };
+            for cmd in cmds.clone() {
+                if let Err(err) = game.execute_cmd(*user, cmd) {
+                    error!("Error executing command: {:?}", err);
+                };
+            }
+            return cmds;
+        }
+
+        let Some(execution_func) = (unsafe { &*&raw const EXECUTION_FUNCTION }) else {
             return vec![];
         };
 
@@ -62,6 +207,21 @@ impl Controller {
 
 #[wasm_bindgen]
 pub async unsafe fn set_computer_func(execute_func: Function) {
-    EXECUTION_FUNCTION = Some(execute_func);
+    unsafe { EXECUTION_FUNCTION = Some(execute_func) };
     warn!("Setting computer function");
 }
+
+/// Optional, called with `(delayed, dropped)` when the rate limit held back commands.
+#[wasm_bindgen]
+pub async unsafe fn set_computer_feedback_func(feedback_func: Function) {
+    unsafe { FEEDBACK_FUNCTION = Some(feedback_func) };
+}
+
+/// Runs a computer module built for the wasm ABI instead of the functions set above, the
+/// same `.wasm` file the native client takes with `--computer`.
+#[wasm_bindgen]
+pub async unsafe fn load_computer_module(bytes: js_sys::Uint8Array) -> Result<(), JsValue> {
+    let module = ComputerModule::instantiate(&bytes.to_vec()).await?;
+    unsafe { COMPUTER_MODULE = Some(module) };
+    Ok(())
+}
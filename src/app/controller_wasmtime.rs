@@ -0,0 +1,145 @@
+//! Runs `.wasm` computers on native builds through an embedded wasmtime runtime.
+//!
+//! The module follows the wasm computer ABI described in the README ("Wasm computers"),
+//! the web client runs the same file with `load_computer_module`. It gets no imports, and
+//! runs with the memory and fuel limits of the `ExecutionBudget`.
+
+use wasmtime::{
+    Config, Engine, Instance, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, Trap,
+    TypedFunc,
+};
+
+use super::controller_select::{ComputerError, ExecutionBudget};
+use super::*;
+use crate::network::SendFeedback;
+
+pub struct WasmComputer {
+    store: Store<StoreLimits>,
+    memory: Memory,
+    alloc: TypedFunc<u32, u32>,
+    execute: TypedFunc<(u32, u32, u32, u32), u64>,
+    feedback: Option<TypedFunc<(u32, u32), ()>>,
+}
+
+impl WasmComputer {
+    pub fn load(path: &Path, budget: &ExecutionBudget) -> Result<Self, ComputerError> {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config).map_err(wasm_error)?;
+        let module = Module::from_file(&engine, path).map_err(wasm_error)?;
+
+        let mut store = Store::new(&engine, limits(budget));
+        store.limiter(|limits| limits);
+        store.set_fuel(budget.wasm_fuel).map_err(wasm_error)?;
+        let instance = Linker::new(&engine)
+            .instantiate(&mut store, &module)
+            .map_err(wasm_error)?;
+
+        let abi_version = instance
+            .get_typed_func::<(), u32>(&mut store, "stellar_bit_abi_version")
+            .map_err(|_| ComputerError::MissingAbi)?
+            .call(&mut store, ())
+            .map_err(wasm_error)?;
+        if abi_version != COMPUTER_ABI_VERSION {
+            return Err(ComputerError::AbiMismatch {
+                expected: COMPUTER_ABI_VERSION,
+                found: abi_version,
+            });
+        }
+
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| ComputerError::Wasm("module doesn't export `memory`".into()))?;
+        let alloc = typed_func(&instance, &mut store, "alloc")?;
+        let execute = typed_func(&instance, &mut store, "execute")?;
+        let feedback = match instance.get_export(&mut store, "feedback") {
+            Some(_) => Some(typed_func(&instance, &mut store, "feedback")?),
+            None => None,
+        };
+
+        Ok(Self {
+            store,
+            memory,
+            alloc,
+            execute,
+            feedback,
+        })
+    }
+
+    /// Runs the module on serialized copies of the game and user. Running out of fuel
+    /// or any other trap leaves the instance in an unknown state, so it has to be dropped.
+    pub fn execute(
+        &mut self,
+        game: &Game,
+        user: &User,
+        feedback: SendFeedback,
+        budget: &ExecutionBudget,
+    ) -> Result<Vec<GameCmd>, ComputerError> {
+        *self.store.data_mut() = limits(budget);
+        self.store.set_fuel(budget.wasm_fuel).map_err(wasm_error)?;
+
+        if let Some(feedback_func) = &self.feedback
+            && !feedback.is_empty()
+        {
+            feedback_func
+                .call(&mut self.store, (feedback.delayed, feedback.dropped))
+                .map_err(trap_error)?;
+        }
+
+        let (game_ptr, game_len) = self.write(&serialize_bytes(game).unwrap())?;
+        let (user_ptr, user_len) = self.write(&serialize_bytes(user).unwrap())?;
+        let packed = self
+            .execute
+            .call(&mut self.store, (game_ptr, game_len, user_ptr, user_len))
+            .map_err(trap_error)?;
+
+        let (cmds_ptr, cmds_len) = ((packed >> 32) as usize, packed as u32 as usize);
+        let mut cmds = vec![0; cmds_len];
+        self.memory
+            .read(&self.store, cmds_ptr, &mut cmds)
+            .map_err(|_| ComputerError::Wasm("commands are out of the module's memory".into()))?;
+        deserialize_bytes(&cmds).map_err(|_| ComputerError::Wasm("malformed commands".into()))
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(u32, u32), ComputerError> {
+        let len = data.len() as u32;
+        let ptr = self.alloc.call(&mut self.store, len).map_err(trap_error)?;
+        self.memory
+            .write(&mut self.store, ptr as usize, data)
+            .map_err(|_| ComputerError::Wasm("`alloc` returned a buffer out of memory".into()))?;
+        Ok((ptr, len))
+    }
+}
+
+fn limits(budget: &ExecutionBudget) -> StoreLimits {
+    StoreLimitsBuilder::new()
+        .memory_size(budget.wasm_memory)
+        .instances(1)
+        .build()
+}
+
+fn typed_func<Params, Results>(
+    instance: &Instance,
+    store: &mut Store<StoreLimits>,
+    name: &str,
+) -> Result<TypedFunc<Params, Results>, ComputerError>
+where
+    Params: wasmtime::WasmParams,
+    Results: wasmtime::WasmResults,
+{
+    instance
+        .get_typed_func(store, name)
+        .map_err(|e| ComputerError::Wasm(format!("`{}`: {}", name, e)))
+}
+
+fn trap_error(e: wasmtime::Error) -> ComputerError {
+    if e.downcast_ref::<Trap>() == Some(&Trap::OutOfFuel) {
+        ComputerError::OutOfFuel
+    } else {
+        wasm_error(e)
+    }
+}
+
+fn wasm_error(e: wasmtime::Error) -> ComputerError {
+    ComputerError::Wasm(format!("{:#}", e))
+}
@@ -0,0 +1,99 @@
+use crate::network::prediction::update_until;
+use crate::network::recording::{read_recording, RecordedResponse};
+
+use super::*;
+
+/// Plays a recorded session back into the local game.
+///
+/// The game is rebuilt from the last full sync before the current position, so the
+/// timeline can be scrubbed in both directions.
+pub struct Replay {
+    responses: Vec<RecordedResponse>,
+    /// Index of the next response to apply.
+    cursor: usize,
+    position: time::Duration,
+    pub playing: bool,
+    pub speed: f32,
+    last_tick: std::time::Instant,
+}
+
+impl Replay {
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        let responses = read_recording(path)?;
+        Ok(Self {
+            responses,
+            cursor: 0,
+            position: time::Duration::ZERO,
+            playing: true,
+            speed: 1.,
+            last_tick: std::time::Instant::now(),
+        })
+    }
+
+    pub fn duration(&self) -> time::Duration {
+        self.responses
+            .last()
+            .map(|response| response.at)
+            .unwrap_or_default()
+    }
+
+    pub fn position(&self) -> time::Duration {
+        self.position
+    }
+
+    /// Moves the playback forward by the real time since the last call.
+    pub fn advance(&mut self, game: &mut Game, user: &mut User) {
+        let elapsed = self.last_tick.elapsed();
+        self.last_tick = std::time::Instant::now();
+        if !self.playing {
+            return;
+        }
+        self.position = (self.position + elapsed.mul_f32(self.speed)).min(self.duration());
+        if self.position == self.duration() {
+            self.playing = false;
+        }
+        self.apply(game, user);
+    }
+
+    pub fn seek(&mut self, position: time::Duration, game: &mut Game, user: &mut User) {
+        self.position = position.min(self.duration());
+        let snapshot = self.responses.iter().rposition(|recorded| {
+            recorded.at <= self.position
+                && matches!(recorded.response, ServerResponse::SyncFullGame(_))
+        });
+        self.cursor = snapshot.unwrap_or(0);
+        if snapshot.is_none() {
+            *game = Game::new();
+        }
+        self.apply(game, user);
+        // sounds and particles of the skipped part would all go off at once
+        std::mem::take(&mut game.events);
+    }
+
+    fn apply(&mut self, game: &mut Game, user: &mut User) {
+        while let Some(recorded) = self.responses.get(self.cursor)
+            && recorded.at <= self.position
+        {
+            match &recorded.response {
+                ServerResponse::SyncFullGame(new_game) => {
+                    *game = new_game.clone();
+                    game.sync.last_update = recorded.at;
+                }
+                ServerResponse::SyncGameCmds(cmds) => {
+                    update_until(game, recorded.at);
+                    // the recording client skipped its own commands, but none of them were
+                    // executed locally during the replay
+                    for (cmd_user, cmd) in cmds {
+                        if let Err(e) = game.execute_cmd(*cmd_user, cmd.clone()) {
+                            warn!("Recorded game cmd {:?} couldn't execute: {:?}", cmd, e);
+                        }
+                    }
+                }
+                ServerResponse::SetUser(new_user) => *user = *new_user,
+                _ => (),
+            }
+            self.cursor += 1;
+        }
+        update_until(game, self.position);
+    }
+}
@@ -0,0 +1,46 @@
+use std::collections::HashMap;
+
+use super::*;
+
+/// Time in which a correction shrinks to about a third.
+const SMOOTHING_TIME: f32 = 0.15;
+/// Corrections larger than this are real teleports and aren't smoothed.
+const SNAP_DISTANCE: f32 = 200.;
+
+/// Spreads the jumps caused by reconciling with the server over a few frames,
+/// so objects glide to their corrected positions instead of teleporting.
+pub struct Smoothing {
+    offsets: HashMap<GameObjectId, Vec2>,
+}
+
+impl Smoothing {
+    pub fn new() -> Self {
+        Self {
+            offsets: HashMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, corrections: HashMap<GameObjectId, Vec2>) {
+        for (id, correction) in corrections {
+            if correction.length() > SNAP_DISTANCE {
+                self.offsets.remove(&id);
+                continue;
+            }
+            *self.offsets.entry(id).or_insert(Vec2::ZERO) += correction;
+        }
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        let decay = (-dt / SMOOTHING_TIME).exp();
+        self.offsets.retain(|_, offset| {
+            *offset *= decay;
+            offset.length() > 0.01
+        });
+    }
+
+    /// How far from its position in the game an object is drawn, zero when it isn't
+    /// being smoothed.
+    pub fn offset(&self, id: &GameObjectId) -> Vec2 {
+        self.offsets.get(id).copied().unwrap_or(Vec2::ZERO)
+    }
+}
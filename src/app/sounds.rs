@@ -1,6 +1,25 @@
 use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink};
+use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, io::Result};
 
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SoundConfig {
+    /// Multiplies the volume of every sound, 0 mutes them.
+    pub volume: f32,
+    /// How quickly sounds fade with the distance from the camera, larger is slower.
+    pub distance_falloff: f32,
+}
+
+impl Default for SoundConfig {
+    fn default() -> Self {
+        Self {
+            volume: 1.,
+            distance_falloff: 100_000.,
+        }
+    }
+}
+
 pub struct SoundManager {
     stream: OutputStream,
     stream_handle: OutputStreamHandle,
@@ -0,0 +1,27 @@
+use std::path::PathBuf;
+
+use clap::{Arg, Command};
+use stellar_bit_client::run_computer_worker;
+
+/// Started by the client for every isolated computer, see `--isolated`.
+fn main() {
+    let matches = Command::new("Stellar Bit Computer Worker")
+        .about("Runs a computer library on behalf of a client, started by the client itself")
+        .arg(
+            Arg::new("computer")
+                .value_name("PATH")
+                .required(true)
+                .help("Computer library to load")
+        )
+        .arg(
+            Arg::new("addr")
+                .value_name("ADDRESS")
+                .required(true)
+                .help("Loopback address the client listens on")
+        )
+        .get_matches();
+
+    let computer = matches.get_one::<String>("computer").unwrap();
+    let addr = matches.get_one::<String>("addr").unwrap();
+    std::process::exit(run_computer_worker(PathBuf::from(computer), addr));
+}
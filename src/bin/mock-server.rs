@@ -0,0 +1,82 @@
+use clap::{Arg, Command};
+use stellar_bit_client::{new_match, MockFaults, MockServer};
+
+#[tokio::main]
+async fn main() {
+    let matches = Command::new("Stellar Bit Mock Server")
+        .about("A local game server for trying out the client without the network")
+        .arg(
+            Arg::new("addr")
+                .long("addr")
+                .value_name("ADDRESS")
+                .default_value("127.0.0.1:8080")
+                .help("Address to listen on")
+        )
+        .arg(
+            Arg::new("players")
+                .long("players")
+                .value_name("N")
+                .default_value("2")
+                .help("Players that start with a star base, clients join as player 0, 1, ...")
+                .value_parser(clap::value_parser!(u64))
+        )
+        .arg(
+            Arg::new("seed")
+                .long("seed")
+                .value_name("SEED")
+                .help("Seed for the starting game, random by default")
+                .value_parser(clap::value_parser!(u64))
+        )
+        .arg(
+            Arg::new("delay-ms")
+                .long("delay-ms")
+                .value_name("MS")
+                .help("Hold back every response this long")
+                .value_parser(clap::value_parser!(u64))
+        )
+        .arg(
+            Arg::new("drop-rate")
+                .long("drop-rate")
+                .value_name("RATE")
+                .help("Chance of a response being dropped, between 0 and 1")
+                .value_parser(clap::value_parser!(f32))
+        )
+        .arg(
+            Arg::new("slow-down-every")
+                .long("slow-down-every")
+                .value_name("N")
+                .help("Ignore every n-th request and answer it with SlowDown")
+                .value_parser(clap::value_parser!(u32).range(1..))
+        )
+        .arg(
+            Arg::new("close-after-ms")
+                .long("close-after-ms")
+                .value_name("MS")
+                .help("Cut every connection this long after it was opened, without a close frame")
+                .value_parser(clap::value_parser!(u64))
+        )
+        .get_matches();
+
+    let faults = MockFaults {
+        delay_ms: matches.get_one::<u64>("delay-ms").copied().unwrap_or(0),
+        drop_rate: matches.get_one::<f32>("drop-rate").copied().unwrap_or(0.),
+        slow_down_every: matches.get_one::<u32>("slow-down-every").copied(),
+        close_after_ms: matches.get_one::<u64>("close-after-ms").copied(),
+    };
+    let seed = matches.get_one::<u64>("seed").copied().unwrap_or_else(rand::random);
+    println!("Seed: {}", seed);
+    let game = new_match(*matches.get_one::<u64>("players").unwrap(), seed);
+    let addr = matches.get_one::<String>("addr").unwrap();
+    let server = match MockServer::bind(addr, game, faults).await {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("Failed to listen on {}: {}", addr, e);
+            std::process::exit(1);
+        }
+    };
+
+    println!("Mock server listening on {}", server.url());
+    println!("Join with: --join \"{} mock 0\"", server.url());
+    let _ = tokio::signal::ctrl_c().await;
+    println!("Stopping with {} clients connected", server.server().client_count());
+}
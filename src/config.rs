@@ -0,0 +1,332 @@
+//! The client's configuration file, shared by the windowed client and headless mode.
+//!
+//! Settings are layered, each layer overriding the one before:
+//! 1. the defaults,
+//! 2. the top level of the TOML file,
+//! 3. the `[profiles.<name>]` section of the selected profile,
+//! 4. `STELLAR_BIT_*` environment variables,
+//! 5. command line flags, applied by the caller.
+//!
+//! Unknown keys in the file are an error, so a typo doesn't go unnoticed.
+//!
+//! An environment variable names a setting with `__` between sections, e.g.
+//! `STELLAR_BIT_SYNC__CMDS_SYNC_MS=200`. The value of a string setting is taken as it is,
+//! so `STELLAR_BIT_SERVER_ADDR=ws://localhost:8080` needs no quotes and a username like
+//! `1234` stays a string. Other values are read as TOML. Variables that don't name a
+//! setting are ignored with a warning. The password is never part of the config, see
+//! `credentials`.
+
+use std::cmp::Ordering;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::*;
+
+const ENV_PREFIX: &str = "STELLAR_BIT_";
+/// Picks the profile when no `--profile` is given.
+pub const PROFILE_ENV: &str = "STELLAR_BIT_PROFILE";
+/// Points at the config file when no `--config` is given.
+pub const CONFIG_ENV: &str = "STELLAR_BIT_CONFIG";
+
+/// Everything that can be set in the config file, see the module docs for the layers.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientConfig {
+    /// Server in the "Network connection" window.
+    pub server_addr: String,
+    /// Central hub login, for `--server-id` and the "Central Hub" window.
+    pub username: Option<String>,
+    /// Log into a `LocalHub` read from this file instead of the central hub.
+    pub local_hub: Option<PathBuf>,
+    /// Computers loaded on start, `[NAME=]PATH` like `--computer`. Headless mode only
+    /// uses them when no `--computer` is given.
+    pub computers: Vec<String>,
+    pub camera: CameraConfig,
+    pub sound: SoundConfig,
+    pub sync: SyncPolicy,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            server_addr: "ws://0.0.0.0:39453".into(),
+            username: None,
+            local_hub: None,
+            computers: vec![],
+            camera: CameraConfig::default(),
+            sound: SoundConfig::default(),
+            sync: SyncPolicy::default(),
+        }
+    }
+}
+
+impl ClientConfig {
+    /// `stellar-bit/config.toml` in the user's config directory.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("stellar-bit").join("config.toml"))
+    }
+
+    /// Reads the config file at `path`, or at the default path when `None`, and applies
+    /// the profile and the environment. A missing file counts as empty, a missing
+    /// profile is an error.
+    pub fn load(path: Option<&Path>, profile: Option<&str>) -> io::Result<Self> {
+        Self::load_with_env(path, profile, std::env::vars())
+    }
+
+    fn load_with_env(
+        path: Option<&Path>,
+        profile: Option<&str>,
+        env: impl IntoIterator<Item = (String, String)>,
+    ) -> io::Result<Self> {
+        let invalid = |reason: String| io::Error::new(io::ErrorKind::InvalidData, reason);
+
+        let path = path.map(Path::to_owned).or_else(Self::default_path);
+        let mut table = match &path {
+            Some(path) => match std::fs::read_to_string(path) {
+                Ok(text) => text
+                    .parse::<toml::Table>()
+                    .map_err(|e| invalid(format!("{:?}: {}", path, e)))?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => toml::Table::new(),
+                Err(e) => return Err(e),
+            },
+            None => toml::Table::new(),
+        };
+
+        let profiles = table.remove("profiles");
+        if let Some(profile) = profile {
+            let section = profiles
+                .as_ref()
+                .and_then(|profiles| profiles.get(profile))
+                .and_then(|section| section.as_table())
+                .ok_or_else(|| invalid(format!("no profile '{}' in {:?}", profile, path)))?;
+            merge(&mut table, section.clone());
+        }
+
+        let settings = Self::settings();
+        for (key, value) in env {
+            let Some(name) = key.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            if [PROFILE_ENV, CONFIG_ENV, PASSPHRASE_ENV, PASSWORD_ENV, COMPUTER_WORKER_ENV].contains(&key.as_str()) {
+                continue;
+            }
+            let keys = name.to_lowercase().split("__").map(str::to_owned).collect::<Vec<_>>();
+            let value = match lookup(&settings, &keys) {
+                Some(toml::Value::String(_)) => toml::Value::String(value),
+                Some(_) => env_value(&value),
+                None => {
+                    eprintln!("{} doesn't name a setting, it is ignored", key);
+                    continue;
+                }
+            };
+            set(&mut table, &keys, value);
+        }
+
+        let config: Self = toml::Value::Table(table)
+            .try_into()
+            .map_err(|e: toml::de::Error| invalid(e.to_string()))?;
+        config.validate().map_err(invalid)?;
+        Ok(config)
+    }
+
+    /// Every setting, the optional ones filled in so that they show up too.
+    fn settings() -> toml::Table {
+        let all = Self {
+            username: Some(String::new()),
+            local_hub: Some(PathBuf::new()),
+            ..Self::default()
+        };
+        toml::Table::try_from(all).expect("the config is a table")
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        self.sync.validate()?;
+        if self.camera.min_zoom.partial_cmp(&self.camera.max_zoom).is_none_or(Ordering::is_gt) {
+            return Err("camera.min_zoom is larger than camera.max_zoom".into());
+        }
+        if !(0. ..).contains(&self.sound.volume) || self.sound.distance_falloff.partial_cmp(&0.) != Some(Ordering::Greater) {
+            return Err("sound.volume can't be negative and sound.distance_falloff has to be positive".into());
+        }
+        Ok(())
+    }
+}
+
+/// Overrides the values of `base` with the ones of `overlay`, section by section.
+fn merge(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => merge(base, overlay),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn lookup<'a>(table: &'a toml::Table, keys: &[String]) -> Option<&'a toml::Value> {
+    let [key, rest @ ..] = keys else {
+        return None;
+    };
+    let value = table.get(key)?;
+    match rest.is_empty() {
+        true => Some(value),
+        false => lookup(value.as_table()?, rest),
+    }
+}
+
+fn set(table: &mut toml::Table, keys: &[String], value: toml::Value) {
+    let [key, rest @ ..] = keys else {
+        return;
+    };
+    if rest.is_empty() {
+        table.insert(key.clone(), value);
+        return;
+    }
+    let section = table
+        .entry(key.clone())
+        .or_insert(toml::Value::Table(toml::Table::new()));
+    if !section.is_table() {
+        *section = toml::Value::Table(toml::Table::new());
+    }
+    if let toml::Value::Table(section) = section {
+        set(section, rest, value);
+    }
+}
+
+/// Reads an environment variable as a TOML value, anything else is taken as a string.
+fn env_value(raw: &str) -> toml::Value {
+    format!("value = {}", raw)
+        .parse::<toml::Table>()
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(raw.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A config file in a directory of its own, removed again on drop.
+    struct TempConfig(PathBuf);
+
+    impl TempConfig {
+        fn new(text: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("stellar-bit-config-{:016x}", rand::random::<u64>()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let path = dir.join("config.toml");
+            std::fs::write(&path, text).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempConfig {
+        fn drop(&mut self) {
+            if let Some(dir) = self.0.parent() {
+                let _ = std::fs::remove_dir_all(dir);
+            }
+        }
+    }
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    const FILE: &str = r#"
+        server_addr = "ws://file:1"
+        computers = ["file.so"]
+
+        [sync]
+        cmds_sync_ms = 100
+        full_sync_ms = 2000
+
+        [profiles.tournament]
+        server_addr = "ws://profile:2"
+
+        [profiles.tournament.sync]
+        cmds_sync_ms = 150
+        min_full_sync_ms = 500
+    "#;
+
+    #[test]
+    fn layers_override_in_order() {
+        let file = TempConfig::new(FILE);
+        let config = ClientConfig::load_with_env(
+            Some(&file.0),
+            Some("tournament"),
+            env(&[("STELLAR_BIT_SYNC__CMDS_SYNC_MS", "200"), ("HOME", "/nowhere")]),
+        )
+        .unwrap();
+
+        let defaults = ClientConfig::default();
+        assert_eq!(config.sound, defaults.sound);
+        assert_eq!(config.sync.max_full_sync_ms, defaults.sync.max_full_sync_ms);
+        assert_eq!(config.computers, vec!["file.so".to_string()]);
+        assert_eq!(config.sync.full_sync_ms, 2000);
+        assert_eq!(config.server_addr, "ws://profile:2");
+        assert_eq!(config.sync.min_full_sync_ms, 500);
+        assert_eq!(config.sync.cmds_sync_ms, 200);
+    }
+
+    #[test]
+    fn profile_applies_only_when_selected() {
+        let file = TempConfig::new(FILE);
+        let config = ClientConfig::load_with_env(Some(&file.0), None, vec![]).unwrap();
+        assert_eq!(config.server_addr, "ws://file:1");
+        assert_eq!(config.sync.cmds_sync_ms, 100);
+    }
+
+    #[test]
+    fn missing_profile_is_an_error() {
+        let file = TempConfig::new(FILE);
+        let e = ClientConfig::load_with_env(Some(&file.0), Some("casual"), vec![]).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(e.to_string().contains("casual"), "{}", e);
+    }
+
+    #[test]
+    fn missing_file_counts_as_empty() {
+        let file = TempConfig::new("");
+        let missing = file.0.with_file_name("missing.toml");
+        let config = ClientConfig::load_with_env(Some(&missing), None, vec![]).unwrap();
+        assert_eq!(config, ClientConfig::default());
+    }
+
+    #[test]
+    fn string_settings_are_taken_verbatim() {
+        let file = TempConfig::new("");
+        let config = ClientConfig::load_with_env(
+            Some(&file.0),
+            None,
+            env(&[
+                ("STELLAR_BIT_USERNAME", "1234"),
+                ("STELLAR_BIT_SERVER_ADDR", "8080"),
+                ("STELLAR_BIT_COMPUTERS", r#"["a.so", "b.wasm"]"#),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(config.username.as_deref(), Some("1234"));
+        assert_eq!(config.server_addr, "8080");
+        assert_eq!(config.computers, vec!["a.so".to_string(), "b.wasm".to_string()]);
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected_in_the_file_and_ignored_in_the_env() {
+        for text in ["sever_addr = \"ws://typo:1\"", "[sync]\ncmds_sync = 100", "[profiles.x]\nvolume = 1"] {
+            let file = TempConfig::new(text);
+            let profile = text.contains("profiles").then_some("x");
+            let e = ClientConfig::load_with_env(Some(&file.0), profile, vec![]).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData, "{}", text);
+        }
+
+        let file = TempConfig::new("");
+        let config = ClientConfig::load_with_env(
+            Some(&file.0),
+            None,
+            env(&[("STELLAR_BIT_SEVER_ADDR", "ws://typo:1"), ("STELLAR_BIT_SYNC__CMDS_SYNC", "1")]),
+        )
+        .unwrap();
+        assert_eq!(config, ClientConfig::default());
+    }
+}
@@ -0,0 +1,278 @@
+//! A central hub login kept on disk, so the password needn't be typed or passed on the
+//! command line every time.
+//!
+//! The file holds the username and password encrypted with ChaCha20-Poly1305, under a key
+//! derived from a passphrase with Argon2. A wrong passphrase or a tampered file fails to
+//! decrypt instead of giving garbage.
+//!
+//! A login can also be remembered without a passphrase: a random one is then kept in a
+//! key file next to the login, so it unlocks on its own. That is obfuscation only. Anyone
+//! who can read the user's files reads the key file too, and with it the password, the
+//! mode `600` of both files being the only protection. Only a passphrase keeps the login
+//! safe from that.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde::{Deserialize, Serialize};
+
+use super::*;
+
+/// Unlocks the saved login without asking, in headless mode and on the client's start.
+pub const PASSPHRASE_ENV: &str = "STELLAR_BIT_PASSPHRASE";
+/// The central hub password, instead of `--password`.
+pub const PASSWORD_ENV: &str = "STELLAR_BIT_PASSWORD";
+
+const FORMAT_VERSION: u32 = 1;
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoreFile {
+    version: u32,
+    salt: Vec<u8>,
+    nonce: Vec<u8>,
+    ciphertext: Vec<u8>,
+}
+
+/// The file a login is saved to, nothing is read until `load`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CredentialStore {
+    path: PathBuf,
+}
+
+impl CredentialStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// `stellar-bit/credentials.json` in the user's config directory.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("stellar-bit").join("credentials.json"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether a login was saved.
+    pub fn exists(&self) -> bool {
+        self.path.is_file()
+    }
+
+    /// The key file of a login remembered without a passphrase.
+    fn key_path(&self) -> PathBuf {
+        self.path.with_extension("key")
+    }
+
+    /// Whether the saved login unlocks without a passphrase, see `remember`.
+    pub fn is_remembered(&self) -> bool {
+        self.exists() && self.key_path().is_file()
+    }
+
+    /// Saves the login under a random passphrase kept in a key file next to it, so
+    /// `unlock` needs nothing from the user. This only obfuscates it, see the module docs.
+    pub fn remember(&self, credentials: &Credentials) -> io::Result<()> {
+        let key = rand::random::<[u8; 32]>()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+        self.save(credentials, &key)?;
+        write_private(&self.key_path(), key.as_bytes())
+    }
+
+    /// Decrypts a login saved with `remember`.
+    pub fn unlock(&self) -> io::Result<Credentials> {
+        let key = std::fs::read_to_string(self.key_path())?;
+        self.load(key.trim())
+    }
+
+    /// Encrypts the login with `passphrase` and replaces whatever was saved before,
+    /// including a key file of `remember`.
+    pub fn save(&self, credentials: &Credentials, passphrase: &str) -> io::Result<()> {
+        if passphrase.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "the passphrase is empty"));
+        }
+        let salt = rand::random::<[u8; 16]>();
+        let nonce = rand::random::<[u8; 12]>();
+        let plaintext = serde_json::to_vec(credentials).map_err(io::Error::other)?;
+        let ciphertext = cipher(passphrase, &salt)?
+            .encrypt(Nonce::from_slice(&nonce), plaintext.as_slice())
+            .map_err(|_| io::Error::other("couldn't encrypt the login"))?;
+        let file = StoreFile {
+            version: FORMAT_VERSION,
+            salt: salt.to_vec(),
+            nonce: nonce.to_vec(),
+            ciphertext,
+        };
+
+        // the old login stays usable when writing the new one fails
+        write_private(&self.path, &serde_json::to_vec(&file).map_err(io::Error::other)?)?;
+        remove_if_exists(&self.key_path())
+    }
+
+    /// Decrypts the saved login, a wrong passphrase is `InvalidData`.
+    pub fn load(&self, passphrase: &str) -> io::Result<Credentials> {
+        let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, reason.to_owned());
+
+        let data = std::fs::read(&self.path)?;
+        let file: StoreFile = serde_json::from_slice(&data).map_err(|_| invalid("not a credential store"))?;
+        if file.version != FORMAT_VERSION {
+            return Err(invalid("unsupported credential store version"));
+        }
+        if file.nonce.len() != 12 {
+            return Err(invalid("not a credential store"));
+        }
+        let plaintext = cipher(passphrase, &file.salt)?
+            .decrypt(Nonce::from_slice(&file.nonce), file.ciphertext.as_slice())
+            .map_err(|_| invalid("wrong passphrase"))?;
+        serde_json::from_slice(&plaintext).map_err(|_| invalid("not a credential store"))
+    }
+
+    /// Deletes the saved login, if there is one.
+    pub fn forget(&self) -> io::Result<()> {
+        remove_if_exists(&self.key_path())?;
+        remove_if_exists(&self.path)
+    }
+}
+
+/// Writes a file only the user can read. The mode is also set when the file already
+/// existed, since opening it doesn't change the mode of an existing file.
+fn write_private(path: &Path, data: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut out = options.open(path)?;
+    #[cfg(unix)]
+    std::fs::set_permissions(path, std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    io::Write::write_all(&mut out, data)
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+fn cipher(passphrase: &str, salt: &[u8]) -> io::Result<ChaCha20Poly1305> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A store in a directory of its own, removed again on drop.
+    struct TempStore(CredentialStore);
+
+    impl TempStore {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("stellar-bit-credentials-{:016x}", rand::random::<u64>()));
+            Self(CredentialStore::new(dir.join("credentials.json")))
+        }
+    }
+
+    impl Drop for TempStore {
+        fn drop(&mut self) {
+            if let Some(dir) = self.0.path().parent() {
+                let _ = std::fs::remove_dir_all(dir);
+            }
+        }
+    }
+
+    fn login() -> Credentials {
+        Credentials {
+            username: "pilot".into(),
+            password: "hunter2".into(),
+        }
+    }
+
+    #[test]
+    fn saved_login_loads_with_its_passphrase() {
+        let store = TempStore::new();
+        store.0.save(&login(), "correct horse").unwrap();
+        assert!(store.0.exists());
+        assert!(!store.0.is_remembered());
+        assert_eq!(store.0.load("correct horse").unwrap(), login());
+    }
+
+    #[test]
+    fn wrong_passphrase_is_invalid_data() {
+        let store = TempStore::new();
+        store.0.save(&login(), "correct horse").unwrap();
+        let e = store.0.load("battery staple").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn tampered_ciphertext_is_rejected() {
+        let store = TempStore::new();
+        store.0.save(&login(), "correct horse").unwrap();
+        let mut file: StoreFile = serde_json::from_slice(&std::fs::read(store.0.path()).unwrap()).unwrap();
+        file.ciphertext[0] ^= 1;
+        std::fs::write(store.0.path(), serde_json::to_vec(&file).unwrap()).unwrap();
+        let e = store.0.load("correct horse").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn remembered_login_unlocks_until_forgotten() {
+        let store = TempStore::new();
+        store.0.remember(&login()).unwrap();
+        assert!(store.0.is_remembered());
+        assert_eq!(store.0.unlock().unwrap(), login());
+
+        // saving with a passphrase replaces the key file
+        store.0.save(&login(), "correct horse").unwrap();
+        assert!(!store.0.is_remembered());
+        assert!(store.0.unlock().is_err());
+
+        store.0.remember(&login()).unwrap();
+        store.0.forget().unwrap();
+        assert!(!store.0.exists());
+        assert!(!store.0.key_path().exists());
+        assert_eq!(store.0.unlock().unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn overwritten_files_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let store = TempStore::new();
+        std::fs::create_dir_all(store.0.path().parent().unwrap()).unwrap();
+        for path in [store.0.path().to_owned(), store.0.key_path()] {
+            std::fs::write(&path, b"{}").unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        }
+        store.0.remember(&login()).unwrap();
+        for path in [store.0.path().to_owned(), store.0.key_path()] {
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600, "{:?}", path);
+        }
+    }
+}
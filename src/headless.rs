@@ -0,0 +1,395 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use tokio::runtime::Runtime;
+
+use super::*;
+
+const STEP_SLEEP: std::time::Duration = std::time::Duration::from_millis(10);
+
+/// Process exit codes used by the headless runner, so supervisors can tell failures apart.
+pub mod exit_code {
+    pub const SUCCESS: i32 = 0;
+    pub const USAGE: i32 = 2;
+    pub const AUTH_FAILED: i32 = 10;
+    pub const CONNECTION_FAILED: i32 = 11;
+    pub const COMPUTER_LOAD_FAILED: i32 = 12;
+    pub const KICKED: i32 = 13;
+}
+
+#[derive(Debug)]
+pub enum HeadlessError {
+    /// Missing or conflicting arguments.
+    Usage(String),
+    InvalidJoinString(String),
+    Hub(String),
+    Connection(String),
+    ConnectionLost(String),
+    ComputerLoad(String),
+    Kicked(String),
+}
+
+impl HeadlessError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            HeadlessError::Usage(_) | HeadlessError::InvalidJoinString(_) => exit_code::USAGE,
+            HeadlessError::Hub(_) => exit_code::AUTH_FAILED,
+            HeadlessError::Connection(_) | HeadlessError::ConnectionLost(_) => {
+                exit_code::CONNECTION_FAILED
+            }
+            HeadlessError::ComputerLoad(_) => exit_code::COMPUTER_LOAD_FAILED,
+            HeadlessError::Kicked(_) => exit_code::KICKED,
+        }
+    }
+}
+
+impl std::fmt::Display for HeadlessError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HeadlessError::Usage(reason) => write!(f, "{}", reason),
+            HeadlessError::InvalidJoinString(reason) => write!(f, "Invalid join string: {}", reason),
+            HeadlessError::Hub(reason) => write!(f, "Failed to connect to central hub: {}", reason),
+            HeadlessError::Connection(reason) => write!(f, "Error connecting to server: {}", reason),
+            HeadlessError::ConnectionLost(reason) => write!(f, "Lost connection to server: {}", reason),
+            HeadlessError::ComputerLoad(reason) => write!(f, "Failed to load computer: {}", reason),
+            HeadlessError::Kicked(reason) => write!(f, "Kicked from server: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for HeadlessError {}
+
+/// How the session's computers are loaded and run.
+#[derive(Clone, Debug)]
+pub struct HeadlessOptions {
+    /// Computers in the order they are executed.
+    pub computers: Vec<ComputerSpec>,
+    /// Run the computers in separate worker processes, so they can't crash the session.
+    pub isolated: bool,
+    pub budget: ExecutionBudget,
+    /// Reload a computer whenever its library file changes.
+    pub hot_reload: bool,
+    /// Record everything the server sends to this file.
+    pub record: Option<PathBuf>,
+    pub sync_policy: SyncPolicy,
+    /// Compare every full game with the local one, see `DesyncOptions`.
+    pub desync: Option<DesyncOptions>,
+    /// The hub `from_hub` logs into.
+    pub hub: HubBackend,
+}
+
+impl HeadlessOptions {
+    pub fn new(computers: Vec<ComputerSpec>) -> Self {
+        Self {
+            computers,
+            isolated: false,
+            budget: ExecutionBudget::default(),
+            hot_reload: true,
+            record: None,
+            sync_policy: SyncPolicy::default(),
+            desync: None,
+            hub: HubBackend::Remote,
+        }
+    }
+}
+
+/// A bot connected to a server without any window, driven by a computer. A spectating
+/// session runs its computers too, but never sends their commands.
+///
+/// Owns its own tokio runtime, so it can be embedded into other tools and stepped
+/// manually with `step`, or run on the current thread with `run_until`.
+pub struct HeadlessSession {
+    rt: Runtime,
+    game: Arc<RwLock<Game>>,
+    user: Arc<RwLock<User>>,
+    controller: Controller,
+    network_connection: NetworkConnection,
+    status_log: Interval,
+    connection_state: ConnectionState,
+}
+
+impl HeadlessSession {
+    /// Logs into `options.hub` and joins the server with the given id.
+    pub fn from_hub(
+        server_id: i64,
+        username: String,
+        password: String,
+        options: HeadlessOptions,
+    ) -> Result<Self, HeadlessError> {
+        let rt = Self::runtime();
+
+        let hub_conn = options
+            .hub
+            .connect(rt.handle(), username.clone(), password)
+            .map_err(HeadlessError::Hub)?;
+        println!("Successfully connected to central hub as '{}'!", username);
+
+        let user_id = hub_conn.user_id().map_err(HeadlessError::Hub)?;
+        let server_access = hub_conn.access_server(server_id).map_err(HeadlessError::Hub)?;
+
+        Self::connect(
+            rt,
+            format!("ws://{}", server_access.server_addr),
+            Some((user_id, server_access.access_token)),
+            options,
+        )
+    }
+
+    /// Joins a server using the string copied from the website: `"address access_token user_id"`.
+    pub fn from_join_string(join: &str, options: HeadlessOptions) -> Result<Self, HeadlessError> {
+        let (addr, token, user_id) =
+            parse_join_string(join).map_err(HeadlessError::InvalidJoinString)?;
+        Self::direct(addr, token, user_id, options)
+    }
+
+    /// Joins a server directly with an already obtained access token.
+    pub fn direct(
+        server_addr: String,
+        access_token: String,
+        user_id: i64,
+        options: HeadlessOptions,
+    ) -> Result<Self, HeadlessError> {
+        Self::connect(Self::runtime(), server_addr, Some((user_id, access_token)), options)
+    }
+
+    /// Watches a server without a player slot, see `NetworkConnection::spectate`.
+    pub fn spectate(server_addr: String, options: HeadlessOptions) -> Result<Self, HeadlessError> {
+        Self::connect(Self::runtime(), server_addr, None, options)
+    }
+
+    /// Joins a server through any transport. Unlike `direct`, a server that can't be
+    /// reached yet isn't an error, the connection is retried like after a disconnect.
+    pub fn with_connector(
+        server_name: String,
+        connector: Box<dyn Connector>,
+        access_token: String,
+        user_id: i64,
+        options: HeadlessOptions,
+    ) -> Result<Self, HeadlessError> {
+        let join = Some((user_id, access_token));
+        Self::open(Self::runtime(), join, options, |_, game, user| {
+            Ok(NetworkConnection::with_connector(server_name, connector, game, user))
+        })
+    }
+
+    /// Joins a game hosted in this process, see `LoopbackServer`.
+    pub fn loopback(
+        server: &LoopbackServer,
+        user_id: i64,
+        options: HeadlessOptions,
+    ) -> Result<Self, HeadlessError> {
+        let connector = Box::new(server.connector());
+        Self::with_connector("loopback".into(), connector, String::new(), user_id, options)
+    }
+
+    fn runtime() -> Runtime {
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+    }
+
+    fn connect(
+        rt: Runtime,
+        server_addr: String,
+        join: Option<(i64, String)>,
+        options: HeadlessOptions,
+    ) -> Result<Self, HeadlessError> {
+        Self::open(rt, join, options, |rt, game, user| {
+            let network_connection = rt
+                .block_on(NetworkConnection::start(server_addr, game, user))
+                .map_err(|e| HeadlessError::Connection(e.to_string()))?;
+            println!("Successfully connected to server!");
+            Ok(network_connection)
+        })
+    }
+
+    /// Loads the computers, then joins over the connection made by `connect` with `join`,
+    /// given as user id and access token, and spectates without it.
+    fn open(
+        rt: Runtime,
+        join: Option<(i64, String)>,
+        options: HeadlessOptions,
+        connect: impl FnOnce(
+            &Runtime,
+            Arc<RwLock<Game>>,
+            Arc<RwLock<User>>,
+        ) -> Result<NetworkConnection, HeadlessError>,
+    ) -> Result<Self, HeadlessError> {
+        let mut init_game = Game::new();
+        init_game.execute_cmd(User::Server, GameCmd::AddPlayer(0)).unwrap();
+
+        let game: Arc<RwLock<Game>> = Arc::new(RwLock::new(init_game));
+        let user = match &join {
+            Some((user_id, _)) => User::Player(*user_id as u64),
+            None => User::Spectator,
+        };
+        let user: Arc<RwLock<User>> = Arc::new(RwLock::new(user));
+
+        let mut controller = Controller::new();
+        controller.budget = options.budget.clone();
+        controller.hot_reload = options.hot_reload;
+        controller
+            .set_isolated(options.isolated)
+            .map_err(|e| HeadlessError::ComputerLoad(e.to_string()))?;
+        for spec in options.computers {
+            let (name, path) = (spec.name.clone(), spec.path.clone());
+            controller.add_computer(spec).map_err(|e| {
+                HeadlessError::ComputerLoad(format!("'{}' ({:?}): {}", name, path, e))
+            })?;
+        }
+
+        let mut network_connection = connect(&rt, game.clone(), user.clone())?;
+        network_connection.set_sync_policy(options.sync_policy);
+        network_connection.set_desync_check(options.desync);
+        if let Some(path) = &options.record
+            && let Err(e) = network_connection.record(path)
+        {
+            warn!("Failed to start recording to {:?}: {}", path, e);
+        }
+        match join {
+            Some((user_id, access_token)) => network_connection.join(user_id as u64, access_token),
+            None => network_connection.spectate(),
+        }
+
+        let connection_state = network_connection.state().clone();
+
+        Ok(Self {
+            rt,
+            game,
+            user,
+            controller,
+            network_connection,
+            status_log: Interval::new(time::Duration::from_secs(60)),
+            connection_state,
+        })
+    }
+
+    pub fn game(&self) -> Arc<RwLock<Game>> {
+        self.game.clone()
+    }
+
+    /// The player the server made us, or `User::Spectator`.
+    pub fn user(&self) -> User {
+        *self.user.read().unwrap()
+    }
+
+    pub fn connection_state(&self) -> &ConnectionState {
+        &self.connection_state
+    }
+
+    pub fn clock_stats(&self) -> ClockStats {
+        self.network_connection.clock_stats()
+    }
+
+    pub fn network_stats(&self) -> NetworkStats {
+        self.network_connection.stats()
+    }
+
+    /// Advances the local game, runs the computers once and sends their commands.
+    pub fn step(&mut self) -> Result<(), HeadlessError> {
+        self.network_connection.poll();
+        if self.network_connection.state() != &self.connection_state {
+            self.connection_state = self.network_connection.state().clone();
+            println!("Connection state: {}", self.connection_state);
+        }
+        for error in self.network_connection.take_errors() {
+            eprintln!("{}", error);
+        }
+        match &self.connection_state {
+            ConnectionState::Failed(reason) => {
+                return Err(HeadlessError::ConnectionLost(reason.clone()));
+            }
+            ConnectionState::Kicked(reason) => return Err(HeadlessError::Kicked(reason.clone())),
+            _ => (),
+        }
+
+        let mut game = self.game.write().unwrap();
+        let user = self.user();
+
+        if game.sync.last_update >= now() {
+            warn!(
+                "Last game update is in the future [+{:?}]??!",
+                game.sync.last_update - now()
+            );
+            return Ok(());
+        }
+
+        let game_dt = now() - game.sync.last_update;
+        game.update(game_dt.as_secs_f32());
+
+        self.controller
+            .report_feedback(self.network_connection.take_send_feedback());
+        let computer_cmds =
+            self.controller
+                .retrieve_cmds_by_computer(&mut game, &user, &egui::Context::default());
+        drop(game);
+
+        // the server ignores commands of spectators anyway
+        if matches!(user, User::Player(_)) {
+            for (computer, cmds) in computer_cmds {
+                self.network_connection.send_cmds(&computer, cmds);
+            }
+        }
+
+        if self.status_log.check() {
+            println!("Clock: {}", self.network_connection.clock_stats());
+            println!("Syncing {}", self.network_connection.sync_status());
+            if let Some(desync_status) = self.network_connection.desync_status() {
+                println!("Desync check: {}", desync_status);
+            }
+            println!("Network: {}", self.network_connection.stats());
+        }
+
+        Ok(())
+    }
+
+    /// Steps the session on the current thread until `cancel` is set or the connection fails.
+    pub fn run_until(&mut self, cancel: &AtomicBool) -> Result<(), HeadlessError> {
+        while !cancel.load(Ordering::Relaxed) {
+            self.step()?;
+            std::thread::sleep(STEP_SLEEP);
+        }
+        Ok(())
+    }
+
+    /// Returns a flag that gets set once the process receives SIGINT or SIGTERM,
+    /// meant to be passed to `run_until`.
+    pub fn shutdown_signal(&self) -> Arc<AtomicBool> {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let cancel_c = cancel.clone();
+        self.rt.spawn(async move {
+            wait_for_signal().await;
+            println!("Received shutdown signal, stopping...");
+            cancel_c.store(true, Ordering::Relaxed);
+        });
+        cancel
+    }
+
+    /// Closes the connection cleanly, unloads the computers and stops the runtime.
+    pub fn shutdown(self) {
+        let Self {
+            rt,
+            mut network_connection,
+            mut controller,
+            ..
+        } = self;
+        network_connection.close();
+        controller.unload();
+        rt.shutdown_timeout(std::time::Duration::from_secs(1));
+    }
+}
+
+async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        if let Ok(mut terminate) = signal(SignalKind::terminate()) {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => (),
+                _ = terminate.recv() => (),
+            }
+            return;
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
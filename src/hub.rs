@@ -0,0 +1,191 @@
+//! The central hub, which logs users in and hands out access to its game servers.
+//!
+//! The "Central Hub" window and `HeadlessSession::from_hub` only go through `HubClient`,
+//! so `LocalHub` can stand in for the real hub during offline development.
+
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use stellar_bit_central_hub_api::HubAPI;
+use tokio::runtime::Handle;
+
+use super::*;
+
+/// A game server listed by the hub.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HubServer {
+    pub id: i64,
+    pub name: String,
+    /// `None` while the server is offline.
+    pub addr: Option<String>,
+}
+
+/// What is needed to join a server, `server_addr` is without the `ws://`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HubAccess {
+    pub server_addr: String,
+    pub access_token: String,
+}
+
+/// A logged in hub connection. The calls block, so they mustn't be made on the runtime's threads.
+pub trait HubClient: Send + Sync {
+    fn username(&self) -> String;
+    fn user_id(&self) -> Result<i64, String>;
+    fn servers(&self) -> Result<Vec<HubServer>, String>;
+    fn access_server(&self, server_id: i64) -> Result<HubAccess, String>;
+}
+
+/// Which hub to log into.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum HubBackend {
+    /// The central hub of stellar-bit.com.
+    #[default]
+    Remote,
+    /// A `LocalHub` read from this file.
+    Local(PathBuf),
+}
+
+impl HubBackend {
+    pub fn connect(
+        &self,
+        runtime: &Handle,
+        username: String,
+        password: String,
+    ) -> Result<Arc<dyn HubClient>, String> {
+        Ok(match self {
+            HubBackend::Remote => Arc::new(RemoteHub::connect(runtime, username, password)?),
+            HubBackend::Local(path) => Arc::new(LocalHub::connect(path, &username, &password)?),
+        })
+    }
+}
+
+struct RemoteHub {
+    api: HubAPI,
+    runtime: Handle,
+}
+
+impl RemoteHub {
+    fn connect(runtime: &Handle, username: String, password: String) -> Result<Self, String> {
+        let api = runtime
+            .block_on(HubAPI::connect(username, password))
+            .map_err(|e| format!("{:?}", e))?;
+        Ok(Self {
+            api,
+            runtime: runtime.clone(),
+        })
+    }
+}
+
+impl HubClient for RemoteHub {
+    fn username(&self) -> String {
+        self.api.username.to_string()
+    }
+
+    fn user_id(&self) -> Result<i64, String> {
+        Ok(self.runtime.block_on(self.api.my_user_data()).id)
+    }
+
+    fn servers(&self) -> Result<Vec<HubServer>, String> {
+        let servers = self.runtime.block_on(self.api.servers());
+        Ok(servers
+            .iter()
+            .map(|server| HubServer {
+                id: server.id,
+                name: server.name.to_string(),
+                addr: server.addr.as_ref().map(|addr| addr.to_string()),
+            })
+            .collect())
+    }
+
+    fn access_server(&self, server_id: i64) -> Result<HubAccess, String> {
+        let access = self.runtime.block_on(self.api.access_server(server_id));
+        Ok(HubAccess {
+            server_addr: access.server_addr.to_string(),
+            access_token: access.access_token.to_string(),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct LocalUser {
+    id: i64,
+    username: String,
+    password: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct LocalServer {
+    id: i64,
+    name: String,
+    #[serde(default)]
+    addr: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct LocalHubFile {
+    users: Vec<LocalUser>,
+    servers: Vec<LocalServer>,
+}
+
+/// A stand-in for the central hub backed by a JSON file of users and servers, see the
+/// README for the format. Access tokens are made up, the mock server accepts any.
+pub struct LocalHub {
+    user: LocalUser,
+    servers: Vec<LocalServer>,
+}
+
+impl LocalHub {
+    pub fn connect(path: &Path, username: &str, password: &str) -> Result<Self, String> {
+        let data = std::fs::read_to_string(path)
+            .map_err(|e| format!("couldn't read local hub {:?}: {}", path, e))?;
+        let file: LocalHubFile = serde_json::from_str(&data)
+            .map_err(|e| format!("invalid local hub {:?}: {}", path, e))?;
+        let user = file
+            .users
+            .into_iter()
+            .find(|user| user.username == username && user.password == password)
+            .ok_or_else(|| "wrong username or password".to_string())?;
+        Ok(Self {
+            user,
+            servers: file.servers,
+        })
+    }
+}
+
+impl HubClient for LocalHub {
+    fn username(&self) -> String {
+        self.user.username.clone()
+    }
+
+    fn user_id(&self) -> Result<i64, String> {
+        Ok(self.user.id)
+    }
+
+    fn servers(&self) -> Result<Vec<HubServer>, String> {
+        Ok(self
+            .servers
+            .iter()
+            .map(|server| HubServer {
+                id: server.id,
+                name: server.name.clone(),
+                addr: server.addr.clone(),
+            })
+            .collect())
+    }
+
+    fn access_server(&self, server_id: i64) -> Result<HubAccess, String> {
+        let server = self
+            .servers
+            .iter()
+            .find(|server| server.id == server_id)
+            .ok_or_else(|| format!("no server with id {}", server_id))?;
+        let server_addr = server
+            .addr
+            .clone()
+            .ok_or_else(|| format!("server '{}' is offline", server.name))?;
+        Ok(HubAccess {
+            server_addr,
+            access_token: format!("local-{}-{}", self.user.id, server.id),
+        })
+    }
+}
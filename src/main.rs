@@ -1,6 +1,6 @@
-use clap::{Command, Arg};
-use stellar_bit_client::{run, run_headless_direct, run_headless_hub};
-use std::path::PathBuf;
+use clap::{Arg, ArgGroup, ArgMatches, Command};
+use stellar_bit_client::{exit_code, run_with, AppOptions, ClientConfig, CredentialStore, Credentials, DesyncOptions, HeadlessError, HubBackend, run_headless_hub, run_headless_join, run_headless_spectate, parse_join_string, simulate, ComputerSpec, HeadlessOptions, SimulationOptions, SyncPolicy, CONFIG_ENV, PASSPHRASE_ENV, PASSWORD_ENV, PROFILE_ENV};
+use std::path::{Path, PathBuf};
 
 fn main() {
     let matches = Command::new("Stellar Bit Client")
@@ -12,7 +12,14 @@ fn main() {
                 .long("join")
                 .value_name("ADDRESS")
                 .help("Direct server connection string (format: \"address access_token user_id\")")
-                .conflicts_with_all(["server-id", "username", "password"])
+                .conflicts_with_all(["server-id", "username", "password", "password-stdin", "remember"])
+        )
+        .arg(
+            Arg::new("spectate")
+                .long("spectate")
+                .value_name("ADDRESS")
+                .help("Watch a server without a player slot, e.g. a league match")
+                .conflicts_with_all(["join", "server-id", "simulate", "replay"])
         )
         .arg(
             Arg::new("server-id")
@@ -20,7 +27,6 @@ fn main() {
                 .value_name("ID")
                 .help("Server ID to join through central hub")
                 .value_parser(clap::value_parser!(i64))
-                .requires_all(["username", "password"])
         )
         .arg(
             Arg::new("username")
@@ -32,7 +38,34 @@ fn main() {
             Arg::new("password")
                 .long("password")
                 .value_name("PASSWORD")
-                .help("Central hub password")
+                .help("Central hub password, visible to other users in the process list; prefer --password-stdin or STELLAR_BIT_PASSWORD")
+        )
+        .arg(
+            Arg::new("password-stdin")
+                .long("password-stdin")
+                .action(clap::ArgAction::SetTrue)
+                .help("Read the central hub password from the first line of stdin")
+                .conflicts_with("password")
+        )
+        .arg(
+            Arg::new("credentials")
+                .long("credentials")
+                .value_name("PATH")
+                .help("Encrypted login file to use instead of stellar-bit/credentials.json in the user config directory")
+        )
+        .arg(
+            Arg::new("remember")
+                .long("remember")
+                .action(clap::ArgAction::SetTrue)
+                .help("Save the central hub login to the encrypted login file, with the passphrase from STELLAR_BIT_PASSPHRASE or else unlocking on its own, which only obfuscates it")
+                .requires_all(["headless", "server-id"])
+        )
+        .arg(
+            Arg::new("local-hub")
+                .long("local-hub")
+                .value_name("PATH")
+                .help("Log into a local stand-in for the central hub, backed by a JSON file of users and servers")
+                .conflicts_with_all(["join", "simulate", "replay"])
         )
         .arg(
             Arg::new("headless")
@@ -40,52 +73,454 @@ fn main() {
                 .action(clap::ArgAction::SetTrue)
                 .help("Run in headless mode without display")
         )
+        .arg(
+            Arg::new("simulate")
+                .long("simulate")
+                .action(clap::ArgAction::SetTrue)
+                .help("Play a local match between the given computers, one player each, and print a JSON report")
+                .conflicts_with_all(["join", "server-id"])
+        )
+        .group(
+            ArgGroup::new("mode")
+                .args(["headless", "simulate"])
+        )
+        .arg(
+            Arg::new("duration")
+                .long("duration")
+                .value_name("SECONDS")
+                .help("Game time after which the simulated match ends without a winner")
+                .value_parser(clap::value_parser!(f32))
+                .requires("simulate")
+        )
+        .arg(
+            Arg::new("dt")
+                .long("dt")
+                .value_name("SECONDS")
+                .help("Game time advanced by every simulation step")
+                .value_parser(clap::value_parser!(f32))
+                .requires("simulate")
+        )
+        .arg(
+            Arg::new("seed")
+                .long("seed")
+                .value_name("SEED")
+                .help("Seed for the starting game of the simulated match, random by default")
+                .value_parser(clap::value_parser!(u64))
+                .requires("simulate")
+        )
+        .arg(
+            Arg::new("report")
+                .long("report")
+                .value_name("PATH")
+                .help("Write the simulation report to a file instead of stdout")
+                .requires("simulate")
+        )
         .arg(
             Arg::new("computer")
                 .long("computer")
-                .value_name("PATH")
-                .help("Path to the computer unit file")
+                .value_name("[NAME=]PATH")
+                .help("Path to a computer unit file, can be given several times; computers run in the given order")
+                .action(clap::ArgAction::Append)
+                .requires("mode")
+        )
+        .arg(
+            Arg::new("scope")
+                .long("scope")
+                .value_name("NAME=TAG[,TAG...]")
+                .help("Limit the named computer to spacecraft with one of the given tags")
+                .action(clap::ArgAction::Append)
+                .requires("computer")
+        )
+        .arg(
+            Arg::new("isolated")
+                .long("isolated")
+                .action(clap::ArgAction::SetTrue)
+                .help("Run the computer in a separate process, so a crash in it doesn't stop the client")
+                .requires("mode")
+        )
+        .arg(
+            Arg::new("budget-ms")
+                .long("budget-ms")
+                .value_name("MS")
+                .help("Time a single computer execution may take before it counts as an overrun")
+                .value_parser(clap::value_parser!(u64))
+                .requires("mode")
+        )
+        .arg(
+            Arg::new("no-hot-reload")
+                .long("no-hot-reload")
+                .action(clap::ArgAction::SetTrue)
+                .help("Don't reload the computer when its file changes")
                 .requires("headless")
         )
+        .arg(
+            Arg::new("record")
+                .long("record")
+                .value_name("PATH")
+                .help("Record everything the server sends to a file, for watching it later with --replay")
+                .conflicts_with("simulate")
+        )
+        .arg(
+            Arg::new("replay")
+                .long("replay")
+                .value_name("PATH")
+                .help("Play back a recorded session")
+                .conflicts_with_all(["join", "server-id", "record", "mode"])
+        )
+        .arg(
+            Arg::new("sync-config")
+                .long("sync-config")
+                .value_name("PATH")
+                .help("TOML file with the sync policy, see the README for the fields")
+                .conflicts_with_all(["simulate", "replay"])
+        )
+        .arg(
+            Arg::new("cmds-sync-ms")
+                .long("cmds-sync-ms")
+                .value_name("MS")
+                .help("Interval of requesting new commands from the server [default: 300]")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .conflicts_with_all(["simulate", "replay"])
+        )
+        .arg(
+            Arg::new("full-sync-ms")
+                .long("full-sync-ms")
+                .value_name("MS")
+                .help("Interval of requesting the full game while it stays in sync [default: 3000]")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .conflicts_with_all(["simulate", "replay"])
+        )
+        .arg(
+            Arg::new("max-messages-per-second")
+                .long("max-messages-per-second")
+                .value_name("N")
+                .help("Messages the client may send per second, commands are batched to stay within it [default: 20]")
+                .value_parser(clap::value_parser!(f32))
+                .conflicts_with_all(["simulate", "replay"])
+        )
+        .arg(
+            Arg::new("max-bytes-per-second")
+                .long("max-bytes-per-second")
+                .value_name("BYTES")
+                .help("Bytes the client may send per second [default: 32000]")
+                .value_parser(clap::value_parser!(f32))
+                .conflicts_with_all(["simulate", "replay"])
+        )
+        .arg(
+            Arg::new("check-desync")
+                .long("check-desync")
+                .action(clap::ArgAction::SetTrue)
+                .help("Compare every full game from the server with the local one and log the differences")
+                .conflicts_with_all(["simulate", "replay"])
+        )
+        .arg(
+            Arg::new("desync-tolerance")
+                .long("desync-tolerance")
+                .value_name("UNITS")
+                .help("Differences in position, health and materials up to this are ignored [default: 1]")
+                .value_parser(clap::value_parser!(f32))
+                .requires("check-desync")
+        )
+        .arg(
+            Arg::new("desync-dump")
+                .long("desync-dump")
+                .value_name("DIR")
+                .help("Write both games and the commands in between to this directory on every desync")
+                .requires("check-desync")
+        )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .value_name("PATH")
+                .help("Config file to read instead of stellar-bit/config.toml in the user config directory")
+        )
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .value_name("NAME")
+                .help("Apply the [profiles.NAME] section of the config file")
+        )
         .get_matches();
 
-    if matches.get_flag("headless") {
-        let computer_path = matches.get_one::<String>("computer")
-            .expect("Computer path is required for headless mode");
+    let config = client_config(&matches);
+
+    if matches.get_flag("simulate") {
+        let mut options = SimulationOptions::new(computer_specs(&matches, &config));
+        options.isolated = matches.get_flag("isolated");
+        if let Some(budget_ms) = matches.get_one::<u64>("budget-ms") {
+            options.budget.limit = std::time::Duration::from_millis(*budget_ms);
+        }
+        if let Some(duration) = matches.get_one::<f32>("duration") {
+            let Ok(duration) = std::time::Duration::try_from_secs_f32(*duration) else {
+                eprintln!("Invalid duration {}", duration);
+                std::process::exit(exit_code::USAGE);
+            };
+            options.duration = duration;
+        }
+        if let Some(dt) = matches.get_one::<f32>("dt") {
+            if !(*dt > 0.) {
+                eprintln!("Invalid dt {}, it has to be positive", dt);
+                std::process::exit(exit_code::USAGE);
+            }
+            options.dt = *dt;
+        }
+        if let Some(seed) = matches.get_one::<u64>("seed") {
+            options.seed = *seed;
+        }
+        eprintln!("Seed: {}", options.seed);
+
+        let report = match simulate(options) {
+            Ok(report) => report.to_json(),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(e.exit_code());
+            }
+        };
+        match matches.get_one::<String>("report") {
+            Some(path) => {
+                if let Err(e) = std::fs::write(path, report) {
+                    eprintln!("Failed to write report to {:?}: {}", path, e);
+                    std::process::exit(1);
+                }
+            }
+            None => println!("{}", report),
+        }
+    } else if matches.get_flag("headless") {
+        let mut options = HeadlessOptions::new(computer_specs(&matches, &config));
+        options.isolated = matches.get_flag("isolated");
+        options.hot_reload = !matches.get_flag("no-hot-reload");
+        options.record = matches.get_one::<String>("record").map(PathBuf::from);
+        options.sync_policy = sync_policy(&matches, &config);
+        options.desync = desync_options(&matches);
+        options.hub = hub_backend(&matches, &config);
+        if let Some(budget_ms) = matches.get_one::<u64>("budget-ms") {
+            options.budget.limit = std::time::Duration::from_millis(*budget_ms);
+        }
 
-        if let Some(join_data) = matches.get_one::<String>("join") {
+        let result = if let Some(server_addr) = matches.get_one::<String>("spectate") {
+            run_headless_spectate(server_addr.clone(), options)
+        } else if let Some(join_data) = matches.get_one::<String>("join") {
             // Direct connection mode
-            let mut parts = join_data.split_whitespace();
-            let addr = parts.next().expect("Missing address in join string");
-            let token = parts.next().expect("Missing token in join string");
-            let user_id = parts.next()
-                .expect("Missing user_id in join string")
-                .parse::<i64>()
-                .expect("Invalid user_id format");
-            
-            run_headless_direct(
-                addr.to_string(),
-                token.to_string(),
-                user_id,
-                PathBuf::from(computer_path)
-            );
-        } else {
+            run_headless_join(join_data, options)
+        } else if let Some(server_id) = matches.get_one::<i64>("server-id") {
             // Central hub connection mode
-            let server_id = matches.get_one::<i64>("server-id")
-                .expect("Either --join or --server-id is required for headless mode");
-            let username = matches.get_one::<String>("username")
-                .expect("Username is required for central hub connection").to_owned();
-            let password = matches.get_one::<String>("password")
-                .expect("Password is required for central hub connection").to_owned();
-            
+            let store = credential_store(&matches);
+            let credentials = hub_credentials(&matches, &config, store.as_ref());
+            if matches.get_flag("remember") {
+                remember(store.as_ref(), &credentials);
+            }
+
             run_headless_hub(
                 *server_id,
-                username,
-                password,
-                PathBuf::from(computer_path)
-            );
+                credentials.username,
+                credentials.password,
+                options
+            )
+        } else {
+            Err(HeadlessError::Usage("Either --join, --server-id or --spectate is required for headless mode".into()))
+        };
+
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            std::process::exit(e.exit_code());
         }
     } else {
-        run();
+        if let Some(Err(e)) = matches.get_one::<String>("join").map(|join| parse_join_string(join)) {
+            eprintln!("Invalid join string: {}", e);
+            std::process::exit(exit_code::USAGE);
+        }
+        run_with(AppOptions {
+            join: matches.get_one::<String>("join").cloned(),
+            record: matches.get_one::<String>("record").map(PathBuf::from),
+            replay: matches.get_one::<String>("replay").map(PathBuf::from),
+            spectate: matches.get_one::<String>("spectate").cloned(),
+            sync_policy: sync_policy(&matches, &config),
+            desync: desync_options(&matches),
+            hub: hub_backend(&matches, &config),
+            server_addr: Some(config.server_addr.clone()),
+            username: matches.get_one::<String>("username").cloned().or(config.username.clone()),
+            password: cli_password(&matches),
+            credentials: credential_store(&matches),
+            passphrase: std::env::var(PASSPHRASE_ENV).ok(),
+            computers: config.computers.iter().map(|computer| parse_computer(computer)).collect(),
+            camera: config.camera,
+            sound: config.sound,
+        });
+    }
+}
+
+/// Reads `--config`, or the default config file, with `--profile` applied.
+fn client_config(matches: &ArgMatches) -> ClientConfig {
+    let path = matches.get_one::<String>("config").cloned().or_else(|| std::env::var(CONFIG_ENV).ok());
+    let profile = matches.get_one::<String>("profile").cloned().or_else(|| std::env::var(PROFILE_ENV).ok());
+    ClientConfig::load(path.as_deref().map(Path::new), profile.as_deref()).unwrap_or_else(|e| {
+        eprintln!("Failed to load config: {}", e);
+        std::process::exit(exit_code::USAGE);
+    })
+}
+
+/// Collects the `--computer [NAME=]PATH` arguments, or the config's computers if there are
+/// none, together with their `--scope NAME=TAGS`.
+fn computer_specs(matches: &ArgMatches, config: &ClientConfig) -> Vec<ComputerSpec> {
+    let mut computers = match matches.get_many::<String>("computer") {
+        Some(computers) => computers.map(|computer| parse_computer(computer)).collect::<Vec<_>>(),
+        None => config.computers.iter().map(|computer| parse_computer(computer)).collect(),
+    };
+    if computers.is_empty() {
+        eprintln!("At least one --computer is required");
+        std::process::exit(exit_code::USAGE);
+    }
+    for scope in matches.get_many::<String>("scope").into_iter().flatten() {
+        let Some((name, tags)) = scope.split_once('=') else {
+            eprintln!("Invalid scope {:?}, expected NAME=TAG[,TAG...]", scope);
+            std::process::exit(exit_code::USAGE);
+        };
+        let Some(computer) = computers.iter_mut().find(|computer| computer.name == name) else {
+            eprintln!("Scope given for unknown computer '{}'", name);
+            std::process::exit(exit_code::USAGE);
+        };
+        computer.scope = Some(tags.split(',').map(|tag| tag.trim().to_owned()).collect());
+    }
+    computers
+}
+
+fn parse_computer(computer: &str) -> ComputerSpec {
+    match computer.split_once('=') {
+        Some((name, path)) => ComputerSpec { name: name.into(), ..ComputerSpec::new(PathBuf::from(path)) },
+        None => ComputerSpec::new(PathBuf::from(computer)),
+    }
+}
+
+/// Loads `--sync-config` if given, the config's `[sync]` otherwise, and applies the
+/// interval flags on top of it.
+fn sync_policy(matches: &ArgMatches, config: &ClientConfig) -> SyncPolicy {
+    let mut policy = match matches.get_one::<String>("sync-config") {
+        Some(path) => SyncPolicy::load(Path::new(path)).unwrap_or_else(|e| {
+            eprintln!("Failed to load sync config {:?}: {}", path, e);
+            std::process::exit(exit_code::USAGE);
+        }),
+        None => config.sync.clone(),
+    };
+    if let Some(cmds_sync_ms) = matches.get_one::<u64>("cmds-sync-ms") {
+        policy.cmds_sync_ms = *cmds_sync_ms;
+    }
+    if let Some(full_sync_ms) = matches.get_one::<u64>("full-sync-ms") {
+        policy.full_sync_ms = *full_sync_ms;
+        policy.min_full_sync_ms = policy.min_full_sync_ms.min(*full_sync_ms);
+        policy.max_full_sync_ms = policy.max_full_sync_ms.max(*full_sync_ms);
+    }
+    for (arg, limit) in [
+        ("max-messages-per-second", &mut policy.rate_limit.messages_per_second),
+        ("max-bytes-per-second", &mut policy.rate_limit.bytes_per_second),
+    ] {
+        if let Some(value) = matches.get_one::<f32>(arg) {
+            if !(*value > 0.) {
+                eprintln!("Invalid --{} {}, it has to be positive", arg, value);
+                std::process::exit(exit_code::USAGE);
+            }
+            *limit = *value;
+        }
+    }
+    policy
+}
+
+fn desync_options(matches: &ArgMatches) -> Option<DesyncOptions> {
+    if !matches.get_flag("check-desync") {
+        return None;
+    }
+    let mut options = DesyncOptions::default();
+    if let Some(tolerance) = matches.get_one::<f32>("desync-tolerance") {
+        if !(*tolerance > 0.) {
+            eprintln!("Invalid desync tolerance {}, it has to be positive", tolerance);
+            std::process::exit(exit_code::USAGE);
+        }
+        options.tolerance = *tolerance;
+    }
+    options.dump_dir = matches.get_one::<String>("desync-dump").map(PathBuf::from);
+    Some(options)
+}
+
+fn hub_backend(matches: &ArgMatches, config: &ClientConfig) -> HubBackend {
+    match matches.get_one::<String>("local-hub").map(PathBuf::from).or(config.local_hub.clone()) {
+        Some(path) => HubBackend::Local(path),
+        None => HubBackend::Remote,
+    }
+}
+
+/// The password from `--password-stdin`, `--password` or `STELLAR_BIT_PASSWORD`.
+fn cli_password(matches: &ArgMatches) -> Option<String> {
+    if matches.get_flag("password-stdin") {
+        let mut password = String::new();
+        if let Err(e) = std::io::stdin().read_line(&mut password) {
+            eprintln!("Failed to read the password from stdin: {}", e);
+            std::process::exit(exit_code::USAGE);
+        }
+        let password = password.trim_end_matches(['\r', '\n']);
+        if password.is_empty() {
+            eprintln!("No password given on stdin");
+            std::process::exit(exit_code::USAGE);
+        }
+        return Some(password.to_owned());
+    }
+    matches.get_one::<String>("password").cloned()
+        .or_else(|| std::env::var(PASSWORD_ENV).ok())
+}
+
+fn credential_store(matches: &ArgMatches) -> Option<CredentialStore> {
+    matches.get_one::<String>("credentials").map(PathBuf::from)
+        .or_else(CredentialStore::default_path)
+        .map(CredentialStore::new)
+}
+
+/// The central hub login from the flags and the config, or else the saved one.
+fn hub_credentials(matches: &ArgMatches, config: &ClientConfig, store: Option<&CredentialStore>) -> Credentials {
+    let username = matches.get_one::<String>("username").cloned().or(config.username.clone());
+    let password = cli_password(matches);
+    match (username, password, store) {
+        (Some(username), Some(password), _) => Credentials { username, password },
+        (username, None, Some(store)) if store.exists() => {
+            let saved = match std::env::var(PASSPHRASE_ENV) {
+                Ok(passphrase) => store.load(&passphrase),
+                Err(_) if store.is_remembered() => store.unlock(),
+                Err(_) => {
+                    eprintln!("Set {} to unlock the saved login in {:?}", PASSPHRASE_ENV, store.path());
+                    std::process::exit(exit_code::USAGE);
+                }
+            };
+            let saved = saved.unwrap_or_else(|e| {
+                eprintln!("Failed to unlock the saved login in {:?}: {}", store.path(), e);
+                std::process::exit(exit_code::USAGE);
+            });
+            if let Some(username) = username.filter(|username| *username != saved.username) {
+                eprintln!("The saved login is for '{}', not '{}'", saved.username, username);
+                std::process::exit(exit_code::USAGE);
+            }
+            saved
+        }
+        _ => {
+            eprintln!("A username and password are required for the central hub, see --password-stdin");
+            std::process::exit(exit_code::USAGE);
+        }
+    }
+}
+
+fn remember(store: Option<&CredentialStore>, credentials: &Credentials) {
+    let Some(store) = store else {
+        eprintln!("No config directory to save the login in, use --credentials");
+        std::process::exit(exit_code::USAGE);
+    };
+    let saved = match std::env::var(PASSPHRASE_ENV) {
+        Ok(passphrase) => store.save(credentials, &passphrase),
+        Err(_) => {
+            eprintln!("{} isn't set, the saved login is only obfuscated", PASSPHRASE_ENV);
+            store.remember(credentials)
+        }
+    };
+    match saved {
+        Ok(()) => println!("Saved the login of '{}' to {:?}", credentials.username, store.path()),
+        Err(e) => {
+            eprintln!("Failed to save the login to {:?}: {}", store.path(), e);
+            std::process::exit(1);
+        }
     }
 }
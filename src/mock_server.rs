@@ -0,0 +1,165 @@
+//! A local websocket server speaking the game protocol, for trying out the client and
+//! testing it end to end without a real server or any network.
+//!
+//! Requests are answered by a `LoopbackServer` holding the authoritative game. On top of
+//! that it can misbehave like a real server under load, see `MockFaults`.
+
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::{accept_async, tungstenite::Message};
+
+use super::*;
+
+/// How often responses are taken from the game and the faults checked.
+const FLUSH_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Ways the server misbehaves, can be changed while it runs with `MockServer::set_faults`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MockFaults {
+    /// Every response is held back this long.
+    pub delay_ms: u64,
+    /// Chance of a response being dropped, between 0 and 1.
+    pub drop_rate: f32,
+    /// Every n-th request of a connection is ignored and answered with `SlowDown`.
+    pub slow_down_every: Option<u32>,
+    /// Connections are cut this long after they were opened, without a close frame.
+    pub close_after_ms: Option<u64>,
+}
+
+/// Stops accepting and drops all connections once dropped.
+pub struct MockServer {
+    addr: SocketAddr,
+    server: LoopbackServer,
+    faults: Arc<Mutex<MockFaults>>,
+    /// Raised to cut all connections opened before.
+    generation: Arc<AtomicU64>,
+    accept_task: tokio::task::JoinHandle<()>,
+}
+
+impl MockServer {
+    /// Listens on `addr` within the current tokio runtime, `"127.0.0.1:0"` picks a free port.
+    pub async fn bind(addr: &str, game: Game, faults: MockFaults) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let addr = listener.local_addr()?;
+        let server = LoopbackServer::new(game);
+        let faults = Arc::new(Mutex::new(faults));
+        let generation = Arc::new(AtomicU64::new(0));
+
+        let accept_task = {
+            let (server, faults, generation) = (server.clone(), faults.clone(), generation.clone());
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(serve(stream, server.clone(), faults.clone(), generation.clone()));
+                }
+            })
+        };
+
+        Ok(Self {
+            addr,
+            server,
+            faults,
+            generation,
+            accept_task,
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The address to connect to, e.g. for `HeadlessSession::direct` or a join string.
+    pub fn url(&self) -> String {
+        format!("ws://{}", self.addr)
+    }
+
+    /// The authoritative game and the players that joined it.
+    pub fn server(&self) -> &LoopbackServer {
+        &self.server
+    }
+
+    pub fn set_faults(&self, faults: MockFaults) {
+        *self.faults.lock().unwrap() = faults;
+    }
+
+    /// Cuts every open connection without a close frame, like a crashed server.
+    pub fn disconnect_all(&self) {
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.accept_task.abort();
+        self.disconnect_all();
+    }
+}
+
+async fn serve(
+    stream: TcpStream,
+    server: LoopbackServer,
+    faults: Arc<Mutex<MockFaults>>,
+    generation: Arc<AtomicU64>,
+) {
+    let Ok(ws_stream) = accept_async(stream).await else {
+        return;
+    };
+    let (mut ws_sender, mut ws_receiver) = ws_stream.split();
+    let client_id = server.add_client();
+    let (opened_at, opened_generation) = (Instant::now(), generation.load(Ordering::Relaxed));
+    let mut requests = 0;
+    let mut outgoing: VecDeque<(Instant, Vec<u8>)> = VecDeque::new();
+    let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+
+    loop {
+        let faults = faults.lock().unwrap().clone();
+        if generation.load(Ordering::Relaxed) != opened_generation
+            || faults
+                .close_after_ms
+                .is_some_and(|close_after| opened_at.elapsed() >= Duration::from_millis(close_after))
+        {
+            break;
+        }
+
+        tokio::select! {
+            msg = ws_receiver.next() => match msg {
+                Some(Ok(Message::Binary(data))) => {
+                    requests += 1;
+                    if faults.slow_down_every.is_some_and(|every| requests % every.max(1) == 0) {
+                        if let Ok(data) = serialize_bytes(&ServerResponse::SlowDown) {
+                            outgoing.push_back((Instant::now(), data));
+                        }
+                    } else {
+                        server.receive(client_id, &data);
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => (),
+            },
+            _ = flush.tick() => (),
+        }
+
+        let delay = Duration::from_millis(faults.delay_ms);
+        for data in server.take_responses(client_id).unwrap_or_default() {
+            if rand::random::<f32>() < faults.drop_rate {
+                continue;
+            }
+            outgoing.push_back((Instant::now() + delay, data));
+        }
+        while let Some((due, _)) = outgoing.front()
+            && *due <= Instant::now()
+        {
+            let (_, data) = outgoing.pop_front().unwrap();
+            if ws_sender.send(Message::Binary(data)).await.is_err() {
+                server.remove_client(client_id);
+                return;
+            }
+        }
+    }
+    // dropping the stream closes the socket without a close frame
+    server.remove_client(client_id);
+}
@@ -1,86 +1,613 @@
 use std::sync::mpsc;
 
-use log::error;
+use log::{error, warn};
 
 use super::*;
 
+mod backoff;
+pub use backoff::Backoff;
+
+mod error;
+pub use error::ConnectionError;
+
+pub mod clock_sync;
+use clock_sync::ClockSync;
+pub use clock_sync::ClockStats;
+
+pub mod prediction;
+use prediction::Prediction;
+
+pub mod stats;
+pub use stats::NetworkStats;
+
+pub mod desync;
+use desync::DesyncCheck;
+pub use desync::DesyncOptions;
+
+pub mod recording;
+use recording::{Recorder, SessionRecorder};
+
+mod outbound;
+use outbound::OutboundQueue;
+pub use outbound::{FeedbackReport, RateLimit, SendFeedback};
+
+mod sync_policy;
+pub use sync_policy::SyncPolicy;
+use sync_policy::SyncScheduler;
+
+pub mod transport;
+pub use transport::{Connector, PendingTransport, Ready, Transport, TransportHandle};
+use transport::WebSocketConnector;
+
+pub mod loopback;
+pub use loopback::LoopbackServer;
+
 #[cfg(target_arch = "wasm32")]
 mod web_network_client;
 #[cfg(target_arch = "wasm32")]
 type NetworkClient = web_network_client::WebNetworkClient;
+#[cfg(target_arch = "wasm32")]
+use web_network_client::ConnectTask;
 
 #[cfg(not(target_arch = "wasm32"))]
 mod desktop_network_client;
 #[cfg(not(target_arch = "wasm32"))]
 type NetworkClient = desktop_network_client::DesktopNetworkClient;
+#[cfg(not(target_arch = "wasm32"))]
+use desktop_network_client::ConnectTask;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Reconnecting {
+        attempt: u32,
+        retry_in: time::Duration,
+    },
+    Failed(String),
+    /// The server closed the connection on purpose, so no reconnect is attempted.
+    Kicked(String),
+}
+
+impl std::fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectionState::Connecting => write!(f, "Connecting"),
+            ConnectionState::Connected => write!(f, "Connected"),
+            ConnectionState::Reconnecting { attempt, retry_in } => {
+                write!(f, "Reconnecting (attempt {}, in {:.1}s)", attempt, retry_in.as_secs_f32())
+            }
+            ConnectionState::Failed(reason) => write!(f, "Failed: {}", reason),
+            ConnectionState::Kicked(reason) => write!(f, "Kicked: {}", reason),
+        }
+    }
+}
+
+/// Splits the join string copied from the website, `"address access_token user_id"`.
+pub fn parse_join_string(join: &str) -> Result<(String, String, i64), String> {
+    let mut parts = join.split_whitespace();
+    let (Some(addr), Some(token), Some(user_id)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err("expected \"address access_token user_id\"".into());
+    };
+    let user_id = user_id
+        .parse::<i64>()
+        .map_err(|_| format!("invalid user_id {:?}", user_id))?;
+    Ok((addr.into(), token.into(), user_id))
+}
+
+/// Events reported by the transport back to the owning `NetworkConnection`, see `TransportHandle`.
+enum NetworkEvent {
+    Disconnected(String),
+    Kicked(String),
+    /// An error the connection survived, e.g. a skipped malformed frame.
+    Error(ConnectionError),
+}
+
+/// Errors kept for `take_errors`, older ones are dropped.
+const MAX_KEPT_ERRORS: usize = 100;
+
+/// State shared between the `NetworkConnection` and its transport.
+#[derive(Clone)]
+struct Shared {
+    game: Arc<RwLock<Game>>,
+    user: Arc<RwLock<User>>,
+    recorder: Recorder,
+    prediction: Arc<Mutex<Prediction>>,
+    clock: Arc<Mutex<ClockSync>>,
+    sync: Arc<Mutex<SyncScheduler>>,
+    desync: Arc<Mutex<Option<DesyncCheck>>>,
+    stats: Arc<Mutex<NetworkStats>>,
+}
 
 pub struct NetworkConnection {
-    client: NetworkClient,
+    client: Option<Box<dyn Transport>>,
+    connector: Box<dyn Connector>,
+    events: mpsc::Receiver<NetworkEvent>,
     pub server_addr: String,
+    shared: Shared,
+    join: Option<(u64, String)>,
+    state: ConnectionState,
+    backoff: Backoff,
+    retry_at: Option<time::Duration>,
+    errors: Vec<ConnectionError>,
+    last_error: Option<ConnectionError>,
+    outbound: OutboundQueue,
+    connect_task: Option<Box<dyn PendingTransport>>,
 }
 
 impl NetworkConnection {
+    /// Connects to the server over a websocket. Fails right away if the server can't be
+    /// reached, only later disconnects are retried.
+    #[cfg(not(target_arch = "wasm32"))]
     pub async fn start(
         server_addr: String,
         game: Arc<RwLock<Game>>,
         user: Arc<RwLock<User>>,
-    ) -> Result<Self, NetworkError> {
-        let client = NetworkClient::connect(&server_addr, game.clone(), user).await?;
-        Ok(Self {
-            client,
+    ) -> Result<Self, ConnectionError> {
+        let connector = WebSocketConnector::new(server_addr.clone());
+        let mut connection = Self::new(server_addr.clone(), Box::new(connector), game, user);
+        let handle = connection.new_handle();
+        let client = NetworkClient::connect(&server_addr, handle).await?;
+        connection.client = Some(Box::new(client));
+        Ok(connection)
+    }
+
+    /// Connects to the server over a websocket without waiting for it, `poll` picks up the
+    /// socket once it is open. Nothing can wait for it on the web, so a server that can't be
+    /// reached is retried like a disconnect there.
+    #[cfg(target_arch = "wasm32")]
+    pub fn start_in_background(
+        server_addr: String,
+        game: Arc<RwLock<Game>>,
+        user: Arc<RwLock<User>>,
+    ) -> Self {
+        let connector = WebSocketConnector::new(server_addr.clone());
+        Self::with_connector(server_addr, Box::new(connector), game, user)
+    }
+
+    /// Connects through any transport. The first attempt is made by `poll`, like a
+    /// reconnect, and `server_addr` only names the server in logs.
+    pub fn with_connector(
+        server_addr: String,
+        connector: Box<dyn Connector>,
+        game: Arc<RwLock<Game>>,
+        user: Arc<RwLock<User>>,
+    ) -> Self {
+        let mut connection = Self::new(server_addr, connector, game, user);
+        let handle = connection.new_handle();
+        connection.connect_task = Some(connection.connector.connect(handle));
+        connection
+    }
+
+    fn new(
+        server_addr: String,
+        connector: Box<dyn Connector>,
+        game: Arc<RwLock<Game>>,
+        user: Arc<RwLock<User>>,
+    ) -> Self {
+        let shared = Shared {
+            game,
+            user,
+            recorder: Recorder::default(),
+            prediction: Arc::new(Mutex::new(Prediction::new())),
+            clock: Arc::new(Mutex::new(ClockSync::new())),
+            sync: Arc::new(Mutex::new(SyncScheduler::new(SyncPolicy::default()))),
+            desync: Arc::new(Mutex::new(None)),
+            stats: Arc::new(Mutex::new(NetworkStats::default())),
+        };
+        Self {
+            client: None,
+            connector,
+            events: mpsc::channel().1,
             server_addr,
-        })
+            shared,
+            join: None,
+            state: ConnectionState::Connecting,
+            backoff: Backoff::default(),
+            retry_at: None,
+            errors: vec![],
+            last_error: None,
+            outbound: OutboundQueue::new(RateLimit::default()),
+            connect_task: None,
+        }
+    }
+
+    /// A handle for the next transport. Events of the previous one are dropped from now on.
+    fn new_handle(&mut self) -> TransportHandle {
+        let (event_sender, events) = mpsc::channel();
+        self.events = events;
+        TransportHandle::new(self.shared.clone(), event_sender)
+    }
+
+    /// Synchronizes the clock, joins with the given credentials and requests the full game.
+    /// The credentials are kept so the same handshake can be repeated after a reconnect.
+    pub fn join(&mut self, user_id: u64, access_token: String) {
+        self.join = Some((user_id, access_token));
+        self.handshake();
     }
+
+    /// Watches the game without a player slot. The handshake, also after a reconnect,
+    /// skips the `Join`, so the server keeps treating the connection as a spectator.
+    pub fn spectate(&mut self) {
+        self.join = None;
+        *self.shared.user.write().unwrap() = User::Spectator;
+        self.handshake();
+    }
+
+    fn handshake(&mut self) {
+        self.sync_clock();
+        if let Some((user_id, access_token)) = self.join.clone() {
+            self.send(ClientRequest::Join(user_id, access_token));
+        }
+        self.send(ClientRequest::FullGameSync);
+        if self.client.is_some() {
+            self.state = ConnectionState::Connected;
+            self.backoff.reset();
+        }
+    }
+
+    pub fn state(&self) -> &ConnectionState {
+        &self.state
+    }
+
+    /// Writes every message received from now on to a recording at `path`,
+    /// see `recording` for the format.
+    pub fn record(&mut self, path: &std::path::Path) -> std::io::Result<()> {
+        *self.shared.recorder.lock().unwrap() = Some(SessionRecorder::create(path)?);
+        println!("Recording session to {:?}", path);
+        Ok(())
+    }
+
+    pub fn stop_recording(&mut self) {
+        *self.shared.recorder.lock().unwrap() = None;
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.shared.recorder.lock().unwrap().is_some()
+    }
+
+    /// Replaces the policy deciding how often commands and full games are synced.
+    pub fn set_sync_policy(&mut self, policy: SyncPolicy) {
+        self.outbound.set_limit(policy.rate_limit.clone());
+        *self.shared.sync.lock().unwrap() = SyncScheduler::new(policy);
+    }
+
+    /// The current sync intervals, as adapted to the server and the game.
+    pub fn sync_status(&self) -> String {
+        self.shared.sync.lock().unwrap().to_string()
+    }
+
+    /// Compares every full game from the server with the local one, see `desync`.
+    /// `None` turns the check off.
+    pub fn set_desync_check(&mut self, options: Option<DesyncOptions>) {
+        *self.shared.desync.lock().unwrap() = options.map(DesyncCheck::new);
+    }
+
+    /// How often the local game diverged, `None` when the check is off.
+    pub fn desync_status(&self) -> Option<String> {
+        self.shared.desync.lock().unwrap().as_ref().map(|check| check.to_string())
+    }
+
+    /// Handles transport events and drives reconnection. Should be called every tick.
+    pub fn poll(&mut self) {
+        if let Some(client) = &mut self.client {
+            client.poll();
+        }
+        while let Ok(event) = self.events.try_recv() {
+            match event {
+                NetworkEvent::Disconnected(reason) => {
+                    self.on_disconnect(reason);
+                }
+                NetworkEvent::Kicked(reason) => {
+                    error!("Kicked from server {}: {}", self.server_addr, reason);
+                    self.client = None;
+                    self.retry_at = None;
+                    self.state = ConnectionState::Kicked(reason);
+                }
+                NetworkEvent::Error(error) => self.report(error),
+            }
+        }
+        // the only place clock syncs are scheduled, on every platform and transport
+        if self.client.is_some() && self.shared.clock.lock().unwrap().poll_request() {
+            self.send(ClientRequest::SyncClock);
+        }
+        self.shared.stats.lock().unwrap().tick();
+        if self.client.is_some() {
+            let requests = self.shared.sync.lock().unwrap().poll();
+            if !requests.is_empty() {
+                self.send_multiple(requests);
+            }
+            self.flush_outbound();
+        }
+        self.poll_reconnect();
+    }
+
+    /// Errors since the last call, including the ones the connection survived.
+    pub fn take_errors(&mut self) -> Vec<ConnectionError> {
+        std::mem::take(&mut self.errors)
+    }
+
+    pub fn last_error(&self) -> Option<&ConnectionError> {
+        self.last_error.as_ref()
+    }
+
+    /// Keeps an error for the UI. Fatal ones were already logged as the disconnect reason.
+    fn report(&mut self, error: ConnectionError) {
+        if !error.is_fatal() {
+            warn!("{}", error);
+        }
+        if self.errors.len() >= MAX_KEPT_ERRORS {
+            self.errors.remove(0);
+        }
+        self.errors.push(error.clone());
+        self.last_error = Some(error);
+    }
+
+    /// Restarts the reconnection process, also after it has failed.
+    /// A connection attempt already under way is kept.
+    pub fn reconnect(&mut self) {
+        self.backoff.reset();
+        let reason = "reconnect requested".to_string();
+        if !self.on_disconnect(reason.clone()) && self.connect_task.is_none() {
+            self.schedule_reconnect(reason);
+        }
+    }
+
+    /// Drops the client and schedules a reconnect. Does nothing without a client, e.g. when
+    /// the transport reports a disconnect a failed send already handled. Returns whether
+    /// there was a client.
+    fn on_disconnect(&mut self, reason: String) -> bool {
+        if self.client.take().is_none() {
+            return false;
+        }
+        warn!("Disconnected from server {}: {}", self.server_addr, reason);
+        self.schedule_reconnect(reason);
+        true
+    }
+
+    fn schedule_reconnect(&mut self, reason: String) {
+        match self.backoff.next_delay() {
+            Some((attempt, retry_in)) => {
+                self.state = ConnectionState::Reconnecting { attempt, retry_in };
+                self.retry_at = Some(now() + retry_in);
+            }
+            None => {
+                error!("Giving up reconnecting to {}: {}", self.server_addr, reason);
+                self.state = ConnectionState::Failed(reason);
+                self.retry_at = None;
+            }
+        }
+    }
+
+    fn poll_reconnect(&mut self) {
+        if self.connect_task.is_some() {
+            let Some(connect_task) = self.connect_task.take_if(|task| task.is_finished()) else {
+                return;
+            };
+            match connect_task.finish() {
+                Ok(client) => {
+                    self.client = Some(client);
+                    self.handshake();
+                    println!("Connected to server {}!", self.server_addr);
+                }
+                Err(e) => {
+                    self.schedule_reconnect(e.to_string());
+                    self.report(e);
+                }
+            }
+            return;
+        }
+        let Some(retry_at) = self.retry_at else {
+            return;
+        };
+        if now() < retry_at {
+            return;
+        }
+        self.retry_at = None;
+        let handle = self.new_handle();
+        self.connect_task = Some(self.connector.connect(handle));
+    }
+
+    /// Closes the transport cleanly. No reconnect is attempted afterwards.
+    pub fn close(&mut self) {
+        self.retry_at = None;
+        self.connect_task = None;
+        if let Some(client) = self.client.take() {
+            client.close();
+        }
+    }
+
+    /// Sends the requests right away, except for commands. Those are queued and sent in
+    /// batches within the `RateLimit`, see `outbound`.
     pub fn send_multiple(&mut self, msgs: Vec<ClientRequest>) {
-        self.client.send_multiple(msgs)
+        if self.client.is_none() {
+            return;
+        }
+        let mut immediate = vec![];
+        for msg in msgs {
+            match msg {
+                ClientRequest::ExecuteGameCmds(cmds) => {
+                    self.predict(&cmds);
+                    self.outbound.push(cmds, None);
+                }
+                msg => immediate.push(msg),
+            }
+        }
+        if !immediate.is_empty() {
+            for msg in &immediate {
+                self.outbound
+                    .charge(serialize_bytes(msg).map_or(0, |bytes| bytes.len()));
+            }
+            self.send_now(immediate);
+        }
+        self.flush_outbound();
     }
     pub fn send(&mut self, msg: ClientRequest) {
-        self.client.send(msg)
+        self.send_multiple(vec![msg]);
+    }
+    /// Queues commands like `send_multiple`, and reports what the rate limit does to them
+    /// under `origin` too, see `FeedbackReport`.
+    pub fn send_cmds(&mut self, origin: &str, cmds: Vec<GameCmd>) {
+        if self.client.is_none() || cmds.is_empty() {
+            return;
+        }
+        self.predict(&cmds);
+        self.outbound.push(cmds, Some(origin));
+        self.flush_outbound();
+    }
+    /// The commands were already executed locally, so they are predicted from now on,
+    /// not from when the queue lets them out.
+    fn predict(&mut self, cmds: &[GameCmd]) {
+        self.shared.prediction.lock().unwrap().push(cmds);
+        if let Some(desync) = self.shared.desync.lock().unwrap().as_mut() {
+            let user = *self.shared.user.read().unwrap();
+            cmds.iter().for_each(|cmd| desync.record_cmd(user, cmd));
+        }
     }
+    fn flush_outbound(&mut self) {
+        while self.client.is_some()
+            && let Some(cmds) = self.outbound.pop_batch()
+        {
+            self.send_now(vec![ClientRequest::ExecuteGameCmds(cmds)]);
+        }
+        let dropped = self.outbound.take_dropped();
+        if !dropped.is_empty() {
+            self.shared.prediction.lock().unwrap().forget(&dropped);
+        }
+    }
+    fn send_now(&mut self, msgs: Vec<ClientRequest>) {
+        let Some(client) = &mut self.client else {
+            return;
+        };
+        for msg in &msgs {
+            if let ClientRequest::ExecuteGameCmds(cmds) = msg {
+                self.shared.prediction.lock().unwrap().sent(cmds);
+            }
+        }
+        if let Err(e) = client.send_multiple(msgs) {
+            self.on_disconnect(e.to_string());
+            self.report(e);
+        }
+    }
+    /// Throws away the clock samples and starts syncing from scratch. The first request
+    /// goes out right away, so it is answered before anything sent after it.
     pub fn sync_clock(&mut self) {
-        self.client.sync_clock();
+        let mut clock = self.shared.clock.lock().unwrap();
+        clock.restart();
+        let due = clock.poll_request();
+        drop(clock);
+        if due {
+            self.send(ClientRequest::SyncClock);
+        }
+    }
+
+    pub fn clock_stats(&self) -> ClockStats {
+        self.shared.clock.lock().unwrap().stats()
+    }
+
+    /// How far objects jumped when the game was reconciled with the server since the
+    /// last call. Meant for the renderer, to move them over smoothly.
+    pub fn take_corrections(&mut self) -> std::collections::HashMap<GameObjectId, Vec2> {
+        self.shared.prediction.lock().unwrap().take_corrections()
+    }
+
+    pub fn stats(&self) -> NetworkStats {
+        self.shared.stats.lock().unwrap().clone()
+    }
+
+    /// How many of the commands were delayed or dropped by the rate limit since the last call.
+    pub fn take_send_feedback(&mut self) -> FeedbackReport {
+        self.outbound.take_feedback()
+    }
+
+    /// Commands waiting for the rate limit.
+    pub fn queued_cmds(&self) -> usize {
+        self.outbound.queued()
     }
 
+    /// Our commands the server hasn't echoed back yet.
+    pub fn pending_cmds(&self) -> usize {
+        self.shared.prediction.lock().unwrap().pending_count()
+    }
+
+}
+
+/// Handles a raw frame from the server. A malformed one is skipped, the error is only
+/// returned to be reported.
+fn receive_data(data: &[u8], shared: &Shared) -> Result<(), ConnectionError> {
+    recording::record(&shared.recorder, data);
+    let response: Result<ServerResponse, _> = deserialize_bytes(data);
+    shared
+        .stats
+        .lock()
+        .unwrap()
+        .record_received(response.as_ref().ok(), data.len());
+    let response = response.map_err(|e| ConnectionError::Decode(format!("{:?}", e)))?;
+    handle_server_response(response, shared);
+    Ok(())
 }
 
 fn handle_server_response(
     response: ServerResponse,
-    game: Arc<RwLock<Game>>,
-    time_delay: Arc<RwLock<i64>>,
-    user: Arc<RwLock<User>>,
-    sync_response_sender: &std::sync::mpsc::Sender<ServerResponse>,
+    shared: &Shared,
 ) -> bool {
     match response {
-        ServerResponse::SyncFullGame(new_game) => {
-            let mut game = game.write().unwrap();
-            *game = new_game;
-            let last_update =
-                (game.sync.last_update.as_millis() as i64 - *time_delay.read().unwrap()) as u64;
+        ServerResponse::SyncFullGame(mut new_game) => {
+            let time_delay = shared.clock.lock().unwrap().time_delay();
+            let last_update = (new_game.sync.last_update.as_millis() as i64 - time_delay) as u64;
             let last_update = time::Duration::from_millis(last_update);
-            game.sync.last_update = last_update;
+            // what is left of the clock error is within the jitter, it mustn't stall the game
+            new_game.sync.last_update = last_update.min(now());
+
+            let mut game = shared.game.write().unwrap();
+            let user = *shared.user.read().unwrap();
+            let mut desync = shared.desync.lock().unwrap();
+            let local_game = desync.is_some().then(|| game.clone());
+            let divergence = shared
+                .prediction
+                .lock()
+                .unwrap()
+                .reconcile(&mut game, new_game, user);
+            shared.sync.lock().unwrap().on_full_sync(divergence);
+            if let (Some(desync), Some(local_game)) = (desync.as_mut(), local_game) {
+                desync.check(&local_game, &game);
+            }
         }
         ServerResponse::SyncGameCmds(cmds) => {
-            let mut game = game.write().unwrap();
-            let user = *user.read().unwrap();
+            let mut game = shared.game.write().unwrap();
+            let user = *shared.user.read().unwrap();
+            let mut prediction = shared.prediction.lock().unwrap();
+            let mut desync = shared.desync.lock().unwrap();
             cmds.into_iter().for_each(|(cmd_user, cmd)| {
+                // our own commands were already executed when they were queued
                 if let (User::Player(player_id), User::Player(cmd_player_id)) = (user, cmd_user) {
                     if cmd_player_id == player_id {
+                        prediction.acknowledge(&cmd);
                         return;
                     }
                 }
+                if let Some(desync) = desync.as_mut() {
+                    desync.record_cmd(cmd_user, &cmd);
+                }
                 if let Err(e) = game.execute_cmd(cmd_user, cmd.clone()) {
                     eprintln!("Gamae cmd from server {:?} couldn't execute: {:?}", cmd, e);
                 }
             });
         }
         ServerResponse::SetUser(new_user) => {
-            *user.write().unwrap() = new_user;
+            *shared.user.write().unwrap() = new_user;
         }
         ServerResponse::SlowDown => {
             error!("Messages are getting ignored (sending too fast)!");
+            shared.sync.lock().unwrap().on_slow_down();
         }
         ServerResponse::SyncClock(remote_clock) => {
-            sync_response_sender.send(ServerResponse::SyncClock(remote_clock)).unwrap();
+            let mut clock = shared.clock.lock().unwrap();
+            if let Some(rtt) = clock.on_response(remote_clock) {
+                shared.stats.lock().unwrap().rtt = rtt;
+            }
+            shared.prediction.lock().unwrap().latency = clock.stats().rtt / 2;
         }
         _ => (),
     };
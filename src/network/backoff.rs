@@ -0,0 +1,88 @@
+use super::*;
+
+/// Exponential backoff used between reconnection attempts.
+pub struct Backoff {
+    pub initial: time::Duration,
+    pub max: time::Duration,
+    pub factor: u32,
+    pub max_attempts: u32,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new(initial: time::Duration, max: time::Duration, factor: u32, max_attempts: u32) -> Self {
+        Self {
+            initial,
+            max,
+            factor,
+            max_attempts,
+            attempt: 0,
+        }
+    }
+
+    /// Returns the attempt number together with the delay to wait before it,
+    /// or `None` once all attempts are used up.
+    pub fn next_delay(&mut self) -> Option<(u32, time::Duration)> {
+        if self.attempt >= self.max_attempts {
+            return None;
+        }
+        let delay = self
+            .initial
+            .checked_mul(self.factor.saturating_pow(self.attempt))
+            .unwrap_or(self.max);
+        self.attempt += 1;
+        Some((self.attempt, delay.min(self.max)))
+    }
+
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(
+            time::Duration::from_millis(500),
+            time::Duration::from_secs(30),
+            2,
+            12,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn delays(backoff: &mut Backoff) -> Vec<(u32, u64)> {
+        std::iter::from_fn(|| backoff.next_delay())
+            .map(|(attempt, delay)| (attempt, delay.as_millis() as u64))
+            .collect()
+    }
+
+    #[test]
+    fn delays_grow_up_to_the_max_then_give_up() {
+        let mut backoff = Backoff::new(time::Duration::from_millis(100), time::Duration::from_secs(1), 3, 5);
+        assert_eq!(delays(&mut backoff), [(1, 100), (2, 300), (3, 900), (4, 1000), (5, 1000)]);
+        assert!(backoff.next_delay().is_none());
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Some((1, time::Duration::from_millis(100))));
+    }
+
+    #[test]
+    fn overflowing_delays_stay_at_the_max() {
+        let mut backoff = Backoff::new(time::Duration::from_secs(1), time::Duration::from_secs(30), u32::MAX, 4);
+        assert_eq!(delays(&mut backoff), [(1, 1000), (2, 30_000), (3, 30_000), (4, 30_000)]);
+    }
+
+    #[test]
+    fn default_gives_up_after_twelve_attempts() {
+        let mut backoff = Backoff::default();
+        let delays = delays(&mut backoff);
+        assert_eq!(delays.len(), 12);
+        assert_eq!(delays[0], (1, 500));
+        assert_eq!(delays[6], (7, 30_000));
+        assert_eq!(delays[11], (12, 30_000));
+    }
+}
@@ -0,0 +1,253 @@
+//! Continuous estimation of the offset between the client and server clocks.
+//!
+//! Works like a simplified NTP: every `SyncClock` round trip gives an offset sample,
+//! and of the last few samples the one with the shortest round trip is trusted the most,
+//! since it had the least room for asymmetric delays. The offset actually used moves
+//! towards the estimate gradually, so game time never jumps because of a new sample.
+
+use std::collections::VecDeque;
+
+use super::*;
+
+/// Samples the filter picks from.
+const FILTER_SAMPLES: usize = 8;
+/// Interval between requests until the filter is full, e.g. right after connecting.
+const BURST_INTERVAL: time::Duration = time::Duration::from_millis(500);
+const INTERVAL: time::Duration = time::Duration::from_secs(10);
+/// Requests without an answer after this time are considered lost.
+const REQUEST_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+/// How fast the used offset follows the estimate, in ms per second.
+const SLEW_RATE: f64 = 50.;
+/// Larger differences are applied at once, slewing would take too long.
+const STEP_THRESHOLD_MS: f64 = 500.;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ClockStats {
+    /// Server clock minus client clock, as currently used.
+    pub offset_ms: i64,
+    pub rtt: time::Duration,
+    pub jitter: time::Duration,
+    pub samples: usize,
+}
+
+impl std::fmt::Display for ClockStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "offset {} ms, rtt {:.1} ms, jitter {:.1} ms",
+            self.offset_ms,
+            self.rtt.as_secs_f32() * 1000.,
+            self.jitter.as_secs_f32() * 1000.
+        )
+    }
+}
+
+struct ClockSample {
+    offset_ms: f64,
+    rtt: time::Duration,
+}
+
+pub struct ClockSync {
+    samples: VecDeque<ClockSample>,
+    /// Send times of the requests waiting for an answer, the server answers in order.
+    in_flight: VecDeque<time::Duration>,
+    estimate_ms: f64,
+    applied_ms: f64,
+    last_slew: time::Duration,
+    next_request: time::Duration,
+    synced: bool,
+    rtt: time::Duration,
+    jitter: time::Duration,
+}
+
+impl ClockSync {
+    pub fn new() -> Self {
+        Self {
+            samples: VecDeque::new(),
+            in_flight: VecDeque::new(),
+            estimate_ms: 0.,
+            applied_ms: 0.,
+            last_slew: now(),
+            next_request: time::Duration::ZERO,
+            synced: false,
+            rtt: time::Duration::ZERO,
+            jitter: time::Duration::ZERO,
+        }
+    }
+
+    /// Starts over with a burst of requests, e.g. after reconnecting.
+    pub fn restart(&mut self) {
+        self.samples.clear();
+        self.in_flight.clear();
+        self.next_request = time::Duration::ZERO;
+        self.synced = false;
+    }
+
+    /// Returns whether a `SyncClock` request should be sent now, and if so
+    /// expects its answer.
+    pub fn poll_request(&mut self) -> bool {
+        let now = now();
+        while let Some(sent) = self.in_flight.front()
+            && now.saturating_sub(*sent) > REQUEST_TIMEOUT
+        {
+            self.in_flight.pop_front();
+        }
+        if now < self.next_request {
+            return false;
+        }
+        self.next_request = now
+            + if self.samples.len() < FILTER_SAMPLES {
+                BURST_INTERVAL
+            } else {
+                INTERVAL
+            };
+        self.in_flight.push_back(now);
+        true
+    }
+
+    /// Returns the round trip of this request, `None` when it wasn't expected.
+    pub fn on_response(&mut self, remote_clock: time::Duration) -> Option<time::Duration> {
+        let received = now();
+        let Some(sent) = self.in_flight.pop_front() else {
+            warn!("Received a clock sync response that wasn't requested");
+            return None;
+        };
+        let rtt = received.saturating_sub(sent);
+        let offset_ms = millis(remote_clock) - millis(sent + rtt / 2);
+        self.samples.push_back(ClockSample { offset_ms, rtt });
+        if self.samples.len() > FILTER_SAMPLES {
+            self.samples.pop_front();
+        }
+
+        let best = self.samples.iter().min_by_key(|sample| sample.rtt).unwrap();
+        self.estimate_ms = best.offset_ms;
+        self.rtt = best.rtt;
+        let variance = self
+            .samples
+            .iter()
+            .map(|sample| (sample.offset_ms - best.offset_ms).powi(2))
+            .sum::<f64>()
+            / self.samples.len() as f64;
+        self.jitter = time::Duration::from_secs_f64(variance.sqrt() / 1000.);
+
+        self.slew(received);
+        if !self.synced || (self.estimate_ms - self.applied_ms).abs() > STEP_THRESHOLD_MS {
+            if self.synced {
+                warn!(
+                    "Server clock moved by {:.0} ms, stepping instead of slewing",
+                    self.estimate_ms - self.applied_ms
+                );
+            }
+            self.applied_ms = self.estimate_ms;
+            self.synced = true;
+        }
+        Some(rtt)
+    }
+
+    /// Server clock minus client clock in milliseconds, to convert server times.
+    pub fn time_delay(&mut self) -> i64 {
+        self.slew(now());
+        self.applied_ms.round() as i64
+    }
+
+    pub fn stats(&mut self) -> ClockStats {
+        ClockStats {
+            offset_ms: self.time_delay(),
+            rtt: self.rtt,
+            jitter: self.jitter,
+            samples: self.samples.len(),
+        }
+    }
+
+    fn slew(&mut self, now: time::Duration) {
+        let max_step = millis(now.saturating_sub(self.last_slew)) / 1000. * SLEW_RATE;
+        self.applied_ms += (self.estimate_ms - self.applied_ms).clamp(-max_step, max_step);
+        self.last_slew = now;
+    }
+}
+
+fn millis(duration: time::Duration) -> f64 {
+    duration.as_secs_f64() * 1000.
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers a request sent `rtt_ms` ago from a server `offset_ms` ahead, the answer
+    /// having taken half the round trip.
+    fn respond(clock: &mut ClockSync, rtt_ms: u64, offset_ms: i64) {
+        let sent = now() - time::Duration::from_millis(rtt_ms);
+        clock.in_flight.push_back(sent);
+        let server_time = sent + time::Duration::from_millis(rtt_ms / 2);
+        let remote_clock = match offset_ms >= 0 {
+            true => server_time + time::Duration::from_millis(offset_ms as u64),
+            false => server_time - time::Duration::from_millis(offset_ms.unsigned_abs()),
+        };
+        clock.on_response(remote_clock).unwrap();
+    }
+
+    fn assert_near(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 5., "{} isn't close to {}", actual, expected);
+    }
+
+    #[test]
+    fn estimate_follows_the_shortest_round_trip() {
+        let mut clock = ClockSync::new();
+        respond(&mut clock, 200, 1000);
+        respond(&mut clock, 20, 300);
+        respond(&mut clock, 100, -500);
+        assert_near(clock.estimate_ms, 300.);
+        assert!(clock.stats().rtt < time::Duration::from_millis(25));
+        assert!(clock.stats().jitter > time::Duration::ZERO);
+
+        // once the best sample is out of the window the next best takes over
+        for _ in 0..FILTER_SAMPLES {
+            respond(&mut clock, 50, 100);
+        }
+        assert_near(clock.estimate_ms, 100.);
+        assert_eq!(clock.stats().samples, FILTER_SAMPLES);
+    }
+
+    #[test]
+    fn first_sample_is_applied_at_once() {
+        let mut clock = ClockSync::new();
+        respond(&mut clock, 20, 300);
+        assert_near(clock.time_delay() as f64, 300.);
+    }
+
+    #[test]
+    fn small_changes_are_slewed_at_the_slew_rate() {
+        let mut clock = ClockSync::new();
+        respond(&mut clock, 40, 0);
+        respond(&mut clock, 20, 200);
+        assert_near(clock.estimate_ms, 200.);
+        assert_near(clock.time_delay() as f64, 0.);
+
+        clock.last_slew -= time::Duration::from_secs(1);
+        assert_near(clock.time_delay() as f64, SLEW_RATE);
+
+        // it stops at the estimate instead of overshooting
+        clock.last_slew -= time::Duration::from_secs(60);
+        assert_near(clock.time_delay() as f64, 200.);
+    }
+
+    #[test]
+    fn large_changes_are_stepped() {
+        let mut clock = ClockSync::new();
+        respond(&mut clock, 40, 0);
+        respond(&mut clock, 20, 2000);
+        assert_near(clock.time_delay() as f64, 2000.);
+    }
+
+    #[test]
+    fn unrequested_responses_are_ignored() {
+        let mut clock = ClockSync::new();
+        assert!(clock.on_response(now()).is_none());
+        assert_eq!(clock.stats().samples, 0);
+
+        assert!(clock.poll_request());
+        assert!(!clock.poll_request());
+        assert!(clock.on_response(now()).is_some());
+    }
+}
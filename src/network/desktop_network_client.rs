@@ -2,133 +2,192 @@ use std::{time::Duration};
 
 use futures_util::{sink::SinkExt, stream::SplitSink, StreamExt};
 use tokio::{net::TcpStream, task::yield_now, sync::mpsc};
-use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{protocol::frame::coding::CloseCode, Message},
+    MaybeTlsStream, WebSocketStream,
+};
 
 use super::*;
 
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+enum Outgoing {
+    Requests(Vec<ClientRequest>),
+    Close,
+}
+
 pub struct DesktopNetworkClient {
     receive_task: tokio::task::JoinHandle<()>,
     send_task: tokio::task::JoinHandle<()>,
-    time_delay: Arc<RwLock<i64>>,
-    sync_response_receiver: std::sync::mpsc::Receiver<ServerResponse>,
-    send_tx: mpsc::Sender<Vec<ClientRequest>>
+    send_tx: mpsc::Sender<Outgoing>,
+    runtime: tokio::runtime::Handle,
 }
 
 impl DesktopNetworkClient {
     pub async fn connect(
         server_addr: &str,
-        game: Arc<RwLock<Game>>,
-        user: Arc<RwLock<User>>,
-    ) -> Result<Self, NetworkError> {
+        handle: TransportHandle,
+    ) -> Result<Self, ConnectionError> {
         let ws_stream = connect_async(server_addr)
             .await
-            .map_err(|_e| NetworkError::WebsocketTrouble)?
+            .map_err(|e| ConnectionError::Connect {
+                addr: server_addr.into(),
+                reason: e.to_string(),
+            })?
             .0;
         let (mut ws_sender, mut ws_receiver) = ws_stream.split();
-        let (sync_response_sender, sync_response_receiver) = std::sync::mpsc::channel();
-
-        let time_delay = Arc::new(RwLock::new(0));
 
-        let (send_tx, mut rx) = mpsc::channel::<Vec<ClientRequest>>(5);
+        let (send_tx, mut rx) = mpsc::channel::<Outgoing>(5);
 
         let receive_task = {
-            let game = game.clone();
-            let time_delay = time_delay.clone();
-            let user = user.clone();
+            let handle = handle.clone();
             tokio::spawn(async move {
                 while let Some(msg) = ws_receiver.next().await {
-                    let msg = msg.unwrap();
-                    let response: ServerResponse = deserialize_bytes(&msg.into_data()).unwrap();
-                    handle_server_response(
-                        response,
-                        game.clone(),
-                        time_delay.clone(),
-                        user.clone(),
-                        &sync_response_sender,
-                    );
+                    let data = match msg {
+                        Ok(Message::Binary(data)) => data,
+                        Ok(Message::Text(_)) => {
+                            handle.error(ConnectionError::Decode("unexpected text frame".into()));
+                            continue;
+                        }
+                        // only a policy violation is a kick, servers and proxies close with
+                        // anything else on a restart
+                        Ok(Message::Close(Some(frame))) => {
+                            let reason = format!("closed by server ({}: {})", frame.code, frame.reason);
+                            match frame.code {
+                                CloseCode::Policy => handle.kicked(reason),
+                                _ => handle.disconnected(reason),
+                            }
+                            return;
+                        }
+                        Ok(Message::Close(None)) => {
+                            handle.disconnected("closed by server");
+                            return;
+                        }
+                        // pings are answered by tungstenite itself
+                        Ok(_) => continue,
+                        Err(e) => {
+                            handle.disconnected(ConnectionError::Receive(e.to_string()).to_string());
+                            return;
+                        }
+                    };
+                    handle.receive(&data);
                 }
-                // loop {
-                //     tokio::time::sleep(Duration::from_millis(50)).await;
-                // }
+                handle.disconnected("connection closed");
             })
         };
 
         let send_task = {
+            let handle = handle.clone();
             tokio::spawn(async move {
-                while let Some(msgs) = rx.recv().await {
+                while let Some(outgoing) = rx.recv().await {
+                    let msgs = match outgoing {
+                        Outgoing::Requests(msgs) => msgs,
+                        Outgoing::Close => {
+                            let _ = ws_sender.send(Message::Close(None)).await;
+                            let _ = ws_sender.close().await;
+                            return;
+                        }
+                    };
                     for msg in &msgs {
-                        ws_sender
-                            .feed(Message::Binary(serialize_bytes(&msg).unwrap()))
-                            .await
-                            .unwrap()
+                        let data = match serialize_bytes(msg) {
+                            Ok(data) => {
+                                handle.sent(msg, data.len());
+                                data
+                            }
+                            Err(e) => {
+                                handle.error(ConnectionError::Encode(format!("{:?}", e)));
+                                continue;
+                            }
+                        };
+                        if let Err(e) = ws_sender.feed(Message::Binary(data)).await {
+                            handle.disconnected(ConnectionError::Send(e.to_string()).to_string());
+                            return;
+                        }
+                    }
+                    if let Err(e) = ws_sender.flush().await {
+                        handle.disconnected(ConnectionError::Send(e.to_string()).to_string());
+                        return;
                     }
-                    ws_sender
-                        .flush()
-                        .await
-                        .unwrap();
                 }
             })
         };
 
-
-
         Ok(Self {
             receive_task,
-            sync_response_receiver,
             send_tx,
-            time_delay,
-            send_task
+            send_task,
+            runtime: tokio::runtime::Handle::current(),
         })
     }
-    pub fn sync_clock(&mut self) {
-        let mut time_delays = vec![0; 15];
-        for time_delay in &mut time_delays {
-            let start = std::time::Instant::now();
-            self.send(ClientRequest::SyncClock);
-            let ServerResponse::SyncClock(mut remote_clock) =
-                self.sync_response_receiver.recv().unwrap()
-            else {
-                panic!("The only time we should get message from sync_response_receiver is when he sends SyncClock resp.");
-            };
-            remote_clock += start.elapsed() / 2;
-            *time_delay = remote_clock.as_millis() as i64 - now().as_millis() as i64;
-            std::thread::sleep(time::Duration::from_millis(100));
-        }
-
-        println!("{:?}", time_delays);
-
-        time_delays.sort();
+}
 
-        let median = time_delays[time_delays.len() / 2];
-        let average = time_delays.iter().sum::<i64>() / time_delays.len() as i64;
+impl Transport for DesktopNetworkClient {
+    fn send_multiple(&mut self, msgs: Vec<ClientRequest>) -> Result<(), ConnectionError> {
+        self.send_tx
+            .blocking_send(Outgoing::Requests(msgs))
+            .map_err(|_| ConnectionError::Closed)
+    }
 
-        let mut sum_of_squares = 0;
-        for time_delay in &time_delays {
-            sum_of_squares += (time_delay - average) * (time_delay - average);
+    /// Sends a close frame and waits a moment for it to leave, so the server sees a clean close.
+    fn close(mut self: Box<Self>) {
+        if self.send_tx.blocking_send(Outgoing::Close).is_err() {
+            return;
         }
-
-        let standard_deviation = ((sum_of_squares / time_delays.len() as i64) as f64).sqrt() as i64;
-        let _ = time_delays
-            .extract_if(.., |time_delay: &mut i64| (*time_delay - median).abs() > standard_deviation)
-            .collect::<Vec<_>>();
-
-        *self.time_delay.write().unwrap() =
-            time_delays.iter().sum::<i64>() / time_delays.len() as i64;
-        println!(
-            "Time delay between client and server: {} ms",
-            self.time_delay.read().unwrap()
-        );
-    }
-    pub fn send(&mut self, msg: ClientRequest) {
-        self.send_multiple(vec![msg]);
-    }
-    pub fn send_multiple(&mut self, msgs: Vec<ClientRequest>) {
-        self.send_tx.blocking_send(msgs).unwrap();
+        let _ = self
+            .runtime
+            .block_on(tokio::time::timeout(CLOSE_TIMEOUT, &mut self.send_task));
     }
 }
 
 impl Drop for DesktopNetworkClient {
+    /// Stops the tasks, so a replaced connection doesn't keep applying what the server
+    /// sends to the game next to the new one.
     fn drop(&mut self) {
         self.receive_task.abort();
+        self.send_task.abort();
+    }
+}
+
+/// A connection attempt running in the background on the tokio runtime.
+pub struct ConnectTask {
+    runtime: tokio::runtime::Handle,
+    server_addr: String,
+    task: tokio::task::JoinHandle<Result<DesktopNetworkClient, ConnectionError>>,
+}
+
+impl ConnectTask {
+    pub fn spawn(
+        runtime: &tokio::runtime::Handle,
+        server_addr: String,
+        handle: TransportHandle,
+    ) -> Self {
+        let task = {
+            let server_addr = server_addr.clone();
+            runtime.spawn(async move { DesktopNetworkClient::connect(&server_addr, handle).await })
+        };
+        Self {
+            runtime: runtime.clone(),
+            server_addr,
+            task,
+        }
+    }
+}
+
+impl PendingTransport for ConnectTask {
+    fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// Blocks until the attempt is over.
+    fn finish(self: Box<Self>) -> Result<Box<dyn Transport>, ConnectionError> {
+        let client = self
+            .runtime
+            .block_on(self.task)
+            .map_err(|e| ConnectionError::Connect {
+                addr: self.server_addr,
+                reason: e.to_string(),
+            })??;
+        Ok(Box::new(client))
     }
 }
@@ -0,0 +1,348 @@
+//! Detection of the local game silently drifting from the server's between full syncs.
+//!
+//! When a full game arrives it is compared with the local game right before the
+//! reconciliation. Both are at the same time and contain the same predicted commands,
+//! so any difference comes from something that ran differently on the client.
+//! A cheap checksum over the quantized state is compared first, the per object
+//! comparison only runs when it differs.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+use super::*;
+
+/// At most this many desyncs are dumped to disk in one session.
+const MAX_DUMPS: u32 = 20;
+/// Diverged objects logged per desync, the rest only shows up in the dumps.
+const MAX_LOGGED_OBJECTS: usize = 10;
+
+/// Checksum of the object ids, positions, health and player materials, all rounded
+/// to multiples of `tolerance`.
+pub fn checksum(game: &Game, tolerance: f32) -> u64 {
+    let quantize = |value: f32| (value / tolerance).round() as i64;
+    let mut hasher = DefaultHasher::new();
+
+    let mut ids = game.game_objects.keys().copied().collect::<Vec<_>>();
+    ids.sort();
+    for id in ids {
+        let game_object = &game.game_objects[&id];
+        let position = game_object.body().position;
+        (id, quantize(position.x), quantize(position.y), quantize(game_object.health()))
+            .hash(&mut hasher);
+    }
+
+    let mut player_ids = game.players.keys().copied().collect::<Vec<_>>();
+    player_ids.sort();
+    for player_id in player_ids {
+        player_id.hash(&mut hasher);
+        for (material, amount) in sorted_materials(game, player_id) {
+            (material, quantize(amount)).hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ObjectDivergence {
+    pub id: GameObjectId,
+    pub distance: f32,
+    pub health: f32,
+}
+
+/// Everything that differs by more than the tolerance between two games.
+#[derive(Clone, Debug, Default)]
+pub struct DesyncReport {
+    pub local_checksum: u64,
+    pub server_checksum: u64,
+    pub objects: Vec<ObjectDivergence>,
+    pub missing_locally: Vec<GameObjectId>,
+    pub missing_on_server: Vec<GameObjectId>,
+    /// Players whose materials differ, with the largest difference of a single material.
+    pub materials: Vec<(u64, f32)>,
+}
+
+impl DesyncReport {
+    pub fn compare(local: &Game, server: &Game, tolerance: f32) -> Self {
+        let mut report = Self {
+            local_checksum: checksum(local, tolerance),
+            server_checksum: checksum(server, tolerance),
+            ..Default::default()
+        };
+        if report.local_checksum == report.server_checksum {
+            return report;
+        }
+
+        for (id, server_object) in &server.game_objects {
+            let Some(local_object) = local.game_objects.get(id) else {
+                report.missing_locally.push(*id);
+                continue;
+            };
+            let divergence = ObjectDivergence {
+                id: *id,
+                distance: (local_object.body().position - server_object.body().position).length(),
+                health: local_object.health() - server_object.health(),
+            };
+            if divergence.distance > tolerance || divergence.health.abs() > tolerance {
+                report.objects.push(divergence);
+            }
+        }
+        report.missing_on_server = local
+            .game_objects
+            .keys()
+            .filter(|id| !server.game_objects.contains_key(id))
+            .copied()
+            .collect();
+
+        for player_id in server.players.keys() {
+            let local_materials = sorted_materials(local, *player_id);
+            let difference = sorted_materials(server, *player_id)
+                .into_iter()
+                .map(|(material, amount)| {
+                    let local_amount = local_materials
+                        .iter()
+                        .find(|(local_material, _)| *local_material == material)
+                        .map_or(0., |(_, amount)| *amount);
+                    (local_amount - amount).abs()
+                })
+                .fold(0f32, f32::max);
+            if difference > tolerance {
+                report.materials.push((*player_id, difference));
+            }
+        }
+
+        report.objects.sort_by(|a, b| b.distance.total_cmp(&a.distance));
+        report.missing_locally.sort();
+        report.missing_on_server.sort();
+        report.materials.sort_by_key(|(player_id, _)| *player_id);
+        report
+    }
+
+    /// Whether anything actually diverged. Checksums can differ for values right at
+    /// a rounding boundary, without any difference above the tolerance.
+    pub fn is_desync(&self) -> bool {
+        !self.objects.is_empty()
+            || !self.missing_locally.is_empty()
+            || !self.missing_on_server.is_empty()
+            || !self.materials.is_empty()
+    }
+
+    fn log(&self) {
+        warn!(
+            "Local game diverged from the server (checksum {:016x}, server {:016x}): \
+             {} objects moved, {} missing locally, {} missing on the server, {} players' materials differ",
+            self.local_checksum,
+            self.server_checksum,
+            self.objects.len(),
+            self.missing_locally.len(),
+            self.missing_on_server.len(),
+            self.materials.len()
+        );
+        for object in self.objects.iter().take(MAX_LOGGED_OBJECTS) {
+            warn!(
+                "  object {}: off by {:.2}, health off by {:.2}",
+                object.id, object.distance, object.health
+            );
+        }
+        if !self.missing_locally.is_empty() {
+            warn!("  missing locally: {:?}", self.missing_locally);
+        }
+        if !self.missing_on_server.is_empty() {
+            warn!("  missing on the server: {:?}", self.missing_on_server);
+        }
+        for (player_id, difference) in &self.materials {
+            warn!("  player {}: materials off by up to {:.2}", player_id, difference);
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DesyncOptions {
+    /// Differences up to this are ignored, in world units for positions and plain units
+    /// for health and materials.
+    pub tolerance: f32,
+    /// Both games and the commands since the previous full game are written here as JSON
+    /// on every desync.
+    pub dump_dir: Option<PathBuf>,
+}
+
+impl Default for DesyncOptions {
+    fn default() -> Self {
+        Self {
+            tolerance: 1.,
+            dump_dir: None,
+        }
+    }
+}
+
+/// Compares every full game with the local one and keeps the commands executed since
+/// the previous full game, so a dump shows what could have caused a desync.
+pub struct DesyncCheck {
+    options: DesyncOptions,
+    cmds: Vec<(User, GameCmd)>,
+    pub checks: u32,
+    pub desyncs: u32,
+    pub last_report: Option<DesyncReport>,
+}
+
+impl DesyncCheck {
+    pub fn new(options: DesyncOptions) -> Self {
+        Self {
+            options,
+            cmds: vec![],
+            checks: 0,
+            desyncs: 0,
+            last_report: None,
+        }
+    }
+
+    pub fn record_cmd(&mut self, user: User, cmd: &GameCmd) {
+        self.cmds.push((user, cmd.clone()));
+    }
+
+    /// `local` is the game right before reconciling, `server` the reconciled one.
+    pub fn check(&mut self, local: &Game, server: &Game) {
+        self.checks += 1;
+        let cmds = std::mem::take(&mut self.cmds);
+        let report = DesyncReport::compare(local, server, self.options.tolerance);
+        if !report.is_desync() {
+            return;
+        }
+        self.desyncs += 1;
+        report.log();
+        if let Some(dump_dir) = &self.options.dump_dir
+            && self.desyncs <= MAX_DUMPS
+        {
+            match dump(dump_dir, self.desyncs, local, server, &cmds) {
+                Ok(()) => warn!("  dumped to {:?} as desync_{}_*.json", dump_dir, self.desyncs),
+                Err(e) => warn!("  failed to dump the desync to {:?}: {}", dump_dir, e),
+            }
+        }
+        self.last_report = Some(report);
+    }
+}
+
+impl std::fmt::Display for DesyncCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} of {} full syncs diverged", self.desyncs, self.checks)?;
+        if let Some(report) = &self.last_report {
+            write!(
+                f,
+                ", last time {} objects by up to {:.2}",
+                report.objects.len(),
+                report.objects.first().map_or(0., |object| object.distance)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn dump(
+    dir: &Path,
+    index: u32,
+    local: &Game,
+    server: &Game,
+    cmds: &[(User, GameCmd)],
+) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let write = |name: &str, json: serde_json::Result<String>| {
+        let json = json.map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        std::fs::write(dir.join(format!("desync_{}_{}.json", index, name)), json)
+    };
+    write("local", serde_json::to_string_pretty(local))?;
+    write("server", serde_json::to_string_pretty(server))?;
+    write("cmds", serde_json::to_string_pretty(cmds))
+}
+
+/// A player's materials in a fixed order, `Material` has no ordering of its own.
+fn sorted_materials(game: &Game, player_id: u64) -> Vec<(String, f32)> {
+    let Some(player) = game.players.get(&player_id) else {
+        return vec![];
+    };
+    let mut materials = player
+        .materials
+        .iter()
+        .map(|(material, amount)| (format!("{:?}", material), *amount))
+        .collect::<Vec<_>>();
+    materials.sort_by(|a, b| a.0.cmp(&b.0));
+    materials
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game() -> Game {
+        crate::new_match(2, 7)
+    }
+
+    fn some_object(game: &Game) -> GameObjectId {
+        *game.game_objects.keys().min().unwrap()
+    }
+
+    fn move_object(game: &mut Game, id: GameObjectId, by: Vec2) {
+        match game.game_objects.get_mut(&id).unwrap() {
+            GameObject::Asteroid(asteroid) => asteroid.body.position += by,
+            GameObject::Spacecraft(spacecraft) => spacecraft.body.position += by,
+            GameObject::StarBase(star_base) => star_base.body.position += by,
+            GameObject::Projectile(projectile) => projectile.body.position += by,
+        }
+    }
+
+    #[test]
+    fn identical_games_match() {
+        let local = game();
+        let report = DesyncReport::compare(&local, &local.clone(), 1.);
+        assert_eq!(report.local_checksum, report.server_checksum);
+        assert!(!report.is_desync());
+    }
+
+    #[test]
+    fn moved_object_is_reported_above_the_tolerance() {
+        let local = game();
+        let id = some_object(&local);
+
+        let mut server = local.clone();
+        move_object(&mut server, id, vec2(0.3, 0.));
+        assert!(!DesyncReport::compare(&local, &server, 1.).is_desync());
+
+        move_object(&mut server, id, vec2(5.7, 8.));
+        let report = DesyncReport::compare(&local, &server, 1.);
+        assert!(report.is_desync());
+        assert_eq!(report.objects.len(), 1);
+        assert_eq!(report.objects[0].id, id);
+        assert!((report.objects[0].distance - 10.).abs() < 0.01);
+        assert!(report.missing_locally.is_empty() && report.missing_on_server.is_empty());
+        assert!(report.materials.is_empty());
+    }
+
+    #[test]
+    fn missing_objects_are_reported_on_the_side_missing_them() {
+        let local = game();
+        let id = some_object(&local);
+
+        let mut server = local.clone();
+        server.game_objects.remove(&id);
+        let report = DesyncReport::compare(&local, &server, 1.);
+        assert_eq!(report.missing_on_server, vec![id]);
+        assert!(report.missing_locally.is_empty() && report.objects.is_empty());
+
+        let report = DesyncReport::compare(&server, &local, 1.);
+        assert_eq!(report.missing_locally, vec![id]);
+        assert!(report.missing_on_server.is_empty() && report.objects.is_empty());
+    }
+
+    #[test]
+    fn materials_difference_is_reported_per_player() {
+        let local = game();
+        let mut server = local.clone();
+        server
+            .execute_cmd(User::Server, GameCmd::GiveMaterials(1, vec![(Material::Iron, 50.)]))
+            .unwrap();
+        let report = DesyncReport::compare(&local, &server, 1.);
+        assert_eq!(report.materials.len(), 1);
+        assert_eq!(report.materials[0].0, 1);
+        assert!((report.materials[0].1 - 50.).abs() < 0.01);
+        assert!(report.objects.is_empty());
+    }
+}
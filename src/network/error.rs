@@ -0,0 +1,47 @@
+use super::*;
+
+/// Everything that can go wrong between the client and the server.
+///
+/// Only `Decode` and `Encode` leave the connection usable, the message in question is
+/// skipped. Everything else ends the connection and triggers a reconnect.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConnectionError {
+    Connect { addr: String, reason: String },
+    Receive(String),
+    Send(String),
+    /// A frame from the server that isn't a valid `ServerResponse`.
+    Decode(String),
+    /// A request that couldn't be serialized.
+    Encode(String),
+    /// The tasks of the connection are gone, e.g. after the socket closed.
+    Closed,
+}
+
+impl ConnectionError {
+    pub fn is_fatal(&self) -> bool {
+        !matches!(self, ConnectionError::Decode(_) | ConnectionError::Encode(_))
+    }
+}
+
+impl std::fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectionError::Connect { addr, reason } => write!(
+                f,
+                "Couldn't connect to {} ({}), check the address and whether the server is running",
+                addr, reason
+            ),
+            ConnectionError::Receive(reason) => write!(f, "Reading from the server failed: {}", reason),
+            ConnectionError::Send(reason) => write!(f, "Sending to the server failed: {}", reason),
+            ConnectionError::Decode(reason) => write!(
+                f,
+                "Skipped a malformed message from the server ({}), the client and server versions may not match",
+                reason
+            ),
+            ConnectionError::Encode(reason) => write!(f, "Skipped a request that couldn't be encoded: {}", reason),
+            ConnectionError::Closed => write!(f, "The connection is closed"),
+        }
+    }
+}
+
+impl std::error::Error for ConnectionError {}
@@ -0,0 +1,233 @@
+//! An in-process server holding the authoritative game itself, reached through the
+//! `Transport` like any other. Requests and responses are still serialized, so the whole
+//! request/response flow runs, just without a socket in between.
+
+use std::collections::{HashMap, VecDeque};
+
+use super::*;
+
+struct LoopbackClient {
+    user: User,
+    /// Commands executed since the client's last `GameCmdsSync`.
+    cmds: Vec<(User, GameCmd)>,
+    /// Responses waiting for the client's next `poll`.
+    responses: VecDeque<Vec<u8>>,
+}
+
+struct ServerState {
+    game: Game,
+    clients: HashMap<u64, LoopbackClient>,
+    next_client: u64,
+}
+
+impl ServerState {
+    /// Brings the game up to the current time, like a server does before handling anything.
+    fn advance(&mut self) {
+        let now = now();
+        if now > self.game.sync.last_update {
+            let dt = now - self.game.sync.last_update;
+            self.game.update(dt.as_secs_f32());
+        }
+    }
+
+    fn handle(&mut self, client_id: u64, request: ClientRequest) {
+        self.advance();
+        let response = match request {
+            ClientRequest::Join(user_id, _) => {
+                if !self.game.players.contains_key(&user_id)
+                    && let Err(e) = self.game.execute_cmd(User::Server, GameCmd::AddPlayer(user_id))
+                {
+                    warn!("Loopback server couldn't add player {}: {:?}", user_id, e);
+                }
+                let user = User::Player(user_id);
+                self.client(client_id).user = user;
+                Some(ServerResponse::SetUser(user))
+            }
+            ClientRequest::FullGameSync => Some(ServerResponse::SyncFullGame(self.game.clone())),
+            ClientRequest::GameCmdsSync => Some(ServerResponse::SyncGameCmds(std::mem::take(
+                &mut self.client(client_id).cmds,
+            ))),
+            ClientRequest::ExecuteGameCmds(cmds) => {
+                let user = self.client(client_id).user;
+                // spectators may not change the game
+                if let User::Player(_) = user {
+                    for cmd in cmds {
+                        if self.game.execute_cmd(user, cmd.clone()).is_ok() {
+                            for client in self.clients.values_mut() {
+                                client.cmds.push((user, cmd.clone()));
+                            }
+                        }
+                    }
+                }
+                None
+            }
+            ClientRequest::SyncClock => Some(ServerResponse::SyncClock(now())),
+            #[allow(unreachable_patterns)]
+            _ => None,
+        };
+        if let Some(response) = response {
+            match serialize_bytes(&response) {
+                Ok(data) => self.client(client_id).responses.push_back(data),
+                Err(e) => warn!("Loopback server couldn't encode a response: {:?}", e),
+            }
+        }
+    }
+
+    fn client(&mut self, client_id: u64) -> &mut LoopbackClient {
+        self.clients.get_mut(&client_id).unwrap()
+    }
+}
+
+/// A local authoritative game that any number of connections can join, e.g. through
+/// `HeadlessSession::loopback`. Clones share the same game.
+#[derive(Clone)]
+pub struct LoopbackServer {
+    state: Arc<Mutex<ServerState>>,
+}
+
+impl LoopbackServer {
+    pub fn new(game: Game) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(ServerState {
+                game,
+                clients: HashMap::new(),
+                next_client: 0,
+            })),
+        }
+    }
+
+    /// The current authoritative game.
+    pub fn game(&self) -> Game {
+        let mut state = self.state.lock().unwrap();
+        state.advance();
+        state.game.clone()
+    }
+
+    /// Applies a command as the server, e.g. to set up a match. Connected clients
+    /// receive it with their next commands sync.
+    pub fn execute_cmd(&self, cmd: GameCmd) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        state.advance();
+        state
+            .game
+            .execute_cmd(User::Server, cmd.clone())
+            .map_err(|e| format!("{:?}", e))?;
+        for client in state.clients.values_mut() {
+            client.cmds.push((User::Server, cmd.clone()));
+        }
+        Ok(())
+    }
+
+    pub fn client_count(&self) -> usize {
+        self.state.lock().unwrap().clients.len()
+    }
+
+    pub fn connector(&self) -> LoopbackConnector {
+        LoopbackConnector {
+            server: self.clone(),
+        }
+    }
+
+    /// Registers a new connection, it is a spectator until it joins.
+    pub(crate) fn add_client(&self) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        let client_id = state.next_client;
+        state.next_client += 1;
+        state.clients.insert(
+            client_id,
+            LoopbackClient {
+                user: User::Spectator,
+                cmds: vec![],
+                responses: VecDeque::new(),
+            },
+        );
+        client_id
+    }
+
+    /// Handles a serialized request of the client, a malformed one is skipped.
+    /// Returns `false` once the client was removed.
+    pub(crate) fn receive(&self, client_id: u64, data: &[u8]) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if !state.clients.contains_key(&client_id) {
+            return false;
+        }
+        match deserialize_bytes(data) {
+            Ok(request) => state.handle(client_id, request),
+            Err(e) => warn!("Loopback server couldn't decode a request: {:?}", e),
+        }
+        true
+    }
+
+    /// The serialized responses for the client since the last call, `None` once it was removed.
+    pub(crate) fn take_responses(&self, client_id: u64) -> Option<VecDeque<Vec<u8>>> {
+        let mut state = self.state.lock().unwrap();
+        let client = state.clients.get_mut(&client_id)?;
+        Some(std::mem::take(&mut client.responses))
+    }
+
+    pub(crate) fn remove_client(&self, client_id: u64) {
+        self.state.lock().unwrap().clients.remove(&client_id);
+    }
+}
+
+pub struct LoopbackConnector {
+    server: LoopbackServer,
+}
+
+impl Connector for LoopbackConnector {
+    fn connect(&self, handle: TransportHandle) -> Box<dyn PendingTransport> {
+        let transport = LoopbackTransport {
+            server: self.server.clone(),
+            client_id: self.server.add_client(),
+            handle,
+        };
+        Box::new(Ready(Ok(Box::new(transport))))
+    }
+}
+
+/// Responses are delivered on `poll`, so they arrive on the next tick like from a server.
+struct LoopbackTransport {
+    server: LoopbackServer,
+    client_id: u64,
+    handle: TransportHandle,
+}
+
+impl Transport for LoopbackTransport {
+    fn send_multiple(&mut self, msgs: Vec<ClientRequest>) -> Result<(), ConnectionError> {
+        for msg in msgs {
+            let data = match serialize_bytes(&msg) {
+                Ok(data) => data,
+                Err(e) => {
+                    self.handle.error(ConnectionError::Encode(format!("{:?}", e)));
+                    continue;
+                }
+            };
+            self.handle.sent(&msg, data.len());
+            if !self.server.receive(self.client_id, &data) {
+                return Err(ConnectionError::Closed);
+            }
+        }
+        Ok(())
+    }
+
+    fn poll(&mut self) {
+        let Some(responses) = self.server.take_responses(self.client_id) else {
+            self.handle.kicked("removed from the loopback server");
+            return;
+        };
+        for data in responses {
+            self.handle.receive(&data);
+        }
+    }
+
+    fn close(self: Box<Self>) {
+        // dropping does it
+    }
+}
+
+impl Drop for LoopbackTransport {
+    /// Leaves the server, also when the connection is replaced without `close`.
+    fn drop(&mut self) {
+        self.server.remove_client(self.client_id);
+    }
+}
@@ -0,0 +1,343 @@
+//! Rate limiting of the commands sent to the server.
+//!
+//! Commands are queued and leave in batches, one `ExecuteGameCmds` per batch, as fast as
+//! two token buckets allow: one for messages and one for bytes per second. Other requests
+//! bypass the queue but still use up the budget, so the server sees the total within it.
+
+use std::collections::{HashMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use super::*;
+
+/// Limits on what is sent to the server, see `OutboundQueue`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimit {
+    pub messages_per_second: f32,
+    pub bytes_per_second: f32,
+    /// Commands waiting longer than this are dropped, the game has moved on.
+    pub max_delay_ms: u64,
+    /// Beyond this the oldest queued commands are dropped.
+    pub max_queued_cmds: usize,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            messages_per_second: 20.,
+            bytes_per_second: 32_000.,
+            max_delay_ms: 2000,
+            max_queued_cmds: 1000,
+        }
+    }
+}
+
+/// What happened to the commands since the computers were last told.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SendFeedback {
+    /// Commands that couldn't leave right away and had to wait for the budget.
+    pub delayed: u32,
+    /// Commands that were never sent.
+    pub dropped: u32,
+}
+
+impl SendFeedback {
+    pub fn is_empty(&self) -> bool {
+        self.delayed == 0 && self.dropped == 0
+    }
+
+    pub fn add(&mut self, other: SendFeedback) {
+        self.delayed += other.delayed;
+        self.dropped += other.dropped;
+    }
+}
+
+/// `SendFeedback` in total and for every origin the commands were queued with.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FeedbackReport {
+    pub total: SendFeedback,
+    /// By the origin given to `NetworkConnection::send_cmds`, e.g. the computer's name.
+    pub by_origin: HashMap<String, SendFeedback>,
+}
+
+impl FeedbackReport {
+    fn count(&mut self, origin: Option<&str>, count: impl Fn(&mut SendFeedback)) {
+        count(&mut self.total);
+        if let Some(origin) = origin {
+            count(self.by_origin.entry(origin.to_owned()).or_default());
+        }
+    }
+}
+
+struct TokenBucket {
+    tokens: f32,
+    rate: f32,
+    capacity: f32,
+    last_refill: time::Duration,
+}
+
+impl TokenBucket {
+    /// Holds up to one second of the rate, so short bursts go out at once.
+    fn new(rate: f32) -> Self {
+        let capacity = rate.max(1.);
+        Self {
+            tokens: capacity,
+            rate,
+            capacity,
+            last_refill: now(),
+        }
+    }
+
+    fn refill(&mut self, now: time::Duration) {
+        let elapsed = now.saturating_sub(self.last_refill).as_secs_f32();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    /// May go below zero, the debt is paid off before anything else is allowed.
+    fn take(&mut self, amount: f32) {
+        self.tokens -= amount;
+    }
+}
+
+struct QueuedCmd {
+    queued_at: time::Duration,
+    origin: Option<String>,
+    cmd: GameCmd,
+    size: usize,
+    /// Whether it was already counted as delayed.
+    delayed: bool,
+}
+
+pub struct OutboundQueue {
+    limit: RateLimit,
+    messages: TokenBucket,
+    bytes: TokenBucket,
+    queue: VecDeque<QueuedCmd>,
+    feedback: FeedbackReport,
+    /// Commands dropped since `take_dropped`.
+    dropped: Vec<GameCmd>,
+}
+
+impl OutboundQueue {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            messages: TokenBucket::new(limit.messages_per_second),
+            bytes: TokenBucket::new(limit.bytes_per_second),
+            queue: VecDeque::new(),
+            feedback: FeedbackReport::default(),
+            dropped: vec![],
+            limit,
+        }
+    }
+
+    /// Applies a new limit, the queued commands stay queued.
+    pub fn set_limit(&mut self, limit: RateLimit) {
+        self.messages = TokenBucket::new(limit.messages_per_second);
+        self.bytes = TokenBucket::new(limit.bytes_per_second);
+        self.limit = limit;
+    }
+
+    /// Queues the commands, their feedback is also counted for `origin` if given.
+    pub fn push(&mut self, cmds: Vec<GameCmd>, origin: Option<&str>) {
+        let queued_at = now();
+        for cmd in cmds {
+            let size = serialize_bytes(&cmd).map_or(0, |bytes| bytes.len());
+            self.queue.push_back(QueuedCmd {
+                queued_at,
+                origin: origin.map(str::to_owned),
+                cmd,
+                size,
+                delayed: false,
+            });
+        }
+        while self.queue.len() > self.limit.max_queued_cmds {
+            let dropped = self.queue.pop_front().unwrap();
+            self.feedback.count(dropped.origin.as_deref(), |feedback| feedback.dropped += 1);
+            self.dropped.push(dropped.cmd);
+        }
+    }
+
+    /// Accounts for a request that was sent without going through the queue.
+    pub fn charge(&mut self, bytes: usize) {
+        let now = now();
+        self.messages.refill(now);
+        self.bytes.refill(now);
+        self.messages.take(1.);
+        self.bytes.take(bytes as f32);
+    }
+
+    /// Takes the next batch of commands, in the order they were queued, if the budget
+    /// allows sending one now.
+    pub fn pop_batch(&mut self) -> Option<Vec<GameCmd>> {
+        let now = now();
+        let max_delay = time::Duration::from_millis(self.limit.max_delay_ms);
+        while let Some(queued) = self.queue.front()
+            && now.saturating_sub(queued.queued_at) > max_delay
+        {
+            let dropped = self.queue.pop_front().unwrap();
+            self.feedback.count(dropped.origin.as_deref(), |feedback| feedback.dropped += 1);
+            self.dropped.push(dropped.cmd);
+        }
+        if self.queue.is_empty() {
+            return None;
+        }
+
+        self.messages.refill(now);
+        self.bytes.refill(now);
+        let mut batch_size = 0;
+        let mut batch_len = 0;
+        if self.messages.tokens >= 1. {
+            for queued in &self.queue {
+                // a command larger than the whole bucket still has to get out eventually
+                let budget = self.bytes.tokens.max(0.) as usize;
+                if batch_size + queued.size > budget
+                    && !(batch_len == 0 && self.bytes.tokens >= self.bytes.capacity)
+                {
+                    break;
+                }
+                batch_size += queued.size;
+                batch_len += 1;
+            }
+        }
+
+        // whatever stays in the queue now is late
+        for queued in self.queue.iter_mut().skip(batch_len) {
+            if !queued.delayed {
+                queued.delayed = true;
+                self.feedback.count(queued.origin.as_deref(), |feedback| feedback.delayed += 1);
+            }
+        }
+        if batch_len == 0 {
+            return None;
+        }
+        self.messages.take(1.);
+        self.bytes.take(batch_size as f32);
+        Some(self.queue.drain(..batch_len).map(|queued| queued.cmd).collect())
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn take_feedback(&mut self) -> FeedbackReport {
+        std::mem::take(&mut self.feedback)
+    }
+
+    pub fn take_dropped(&mut self) -> Vec<GameCmd> {
+        std::mem::take(&mut self.dropped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cmds(count: usize) -> Vec<GameCmd> {
+        (0..count).map(|_| GameCmd::AddPlayer(1)).collect()
+    }
+
+    fn cmd_size() -> usize {
+        serialize_bytes(&GameCmd::AddPlayer(1)).unwrap().len()
+    }
+
+    /// Plenty of budget, the tests lower the limit they are about.
+    fn limit() -> RateLimit {
+        RateLimit {
+            messages_per_second: 1000.,
+            bytes_per_second: 1_000_000.,
+            max_delay_ms: 60_000,
+            max_queued_cmds: 1000,
+        }
+    }
+
+    #[test]
+    fn message_budget_delays_the_rest_once() {
+        let mut queue = OutboundQueue::new(RateLimit {
+            messages_per_second: 2.,
+            ..limit()
+        });
+        for _ in 0..2 {
+            queue.push(cmds(1), Some("miner"));
+            assert_eq!(queue.pop_batch().map(|batch| batch.len()), Some(1));
+        }
+        queue.push(cmds(1), Some("miner"));
+        queue.push(cmds(1), None);
+        assert!(queue.pop_batch().is_none());
+        assert!(queue.pop_batch().is_none());
+
+        let feedback = queue.take_feedback();
+        assert_eq!(feedback.total, SendFeedback { delayed: 2, dropped: 0 });
+        assert_eq!(feedback.by_origin["miner"], SendFeedback { delayed: 1, dropped: 0 });
+
+        // a second later the bucket has refilled and both leave in one batch
+        queue.messages.last_refill = queue.messages.last_refill.saturating_sub(time::Duration::from_secs(1));
+        assert_eq!(queue.pop_batch().map(|batch| batch.len()), Some(2));
+        assert_eq!(queue.queued(), 0);
+        assert!(queue.take_feedback().total.is_empty());
+    }
+
+    #[test]
+    fn byte_budget_splits_batches() {
+        let mut queue = OutboundQueue::new(RateLimit {
+            bytes_per_second: cmd_size() as f32 * 2.5,
+            ..limit()
+        });
+        queue.push(cmds(3), None);
+        assert_eq!(queue.pop_batch().map(|batch| batch.len()), Some(2));
+        assert!(queue.pop_batch().is_none());
+        assert_eq!(queue.queued(), 1);
+        assert_eq!(queue.take_feedback().total.delayed, 1);
+    }
+
+    #[test]
+    fn charged_requests_use_up_the_budget() {
+        let mut queue = OutboundQueue::new(RateLimit {
+            messages_per_second: 1.,
+            ..limit()
+        });
+        queue.charge(0);
+        queue.push(cmds(1), None);
+        assert!(queue.pop_batch().is_none());
+    }
+
+    #[test]
+    fn excess_and_late_commands_are_dropped() {
+        let mut queue = OutboundQueue::new(RateLimit {
+            max_queued_cmds: 2,
+            ..limit()
+        });
+        queue.push(cmds(3), Some("miner"));
+        assert_eq!(queue.queued(), 2);
+        assert_eq!(queue.take_dropped().len(), 1);
+
+        for queued in &mut queue.queue {
+            queued.queued_at = queued.queued_at.saturating_sub(time::Duration::from_secs(61));
+        }
+        assert!(queue.pop_batch().is_none());
+        assert_eq!(queue.queued(), 0);
+        assert_eq!(queue.take_dropped().len(), 2);
+        let feedback = queue.take_feedback();
+        assert_eq!(feedback.total, SendFeedback { delayed: 0, dropped: 3 });
+        assert_eq!(feedback.by_origin["miner"].dropped, 3);
+    }
+
+    #[test]
+    fn oversized_command_leaves_alone_on_a_full_bucket() {
+        let mut queue = OutboundQueue::new(RateLimit {
+            bytes_per_second: 1.,
+            ..limit()
+        });
+        assert!(cmd_size() > 1);
+        queue.push(cmds(2), None);
+        assert_eq!(queue.pop_batch().map(|batch| batch.len()), Some(1));
+
+        // the debt is paid off before the next one may go
+        assert!(queue.bytes.tokens < 0.);
+        assert!(queue.pop_batch().is_none());
+        queue.bytes.tokens = queue.bytes.capacity;
+        assert_eq!(queue.pop_batch().map(|batch| batch.len()), Some(1));
+        assert_eq!(queue.take_dropped().len(), 0);
+    }
+}
@@ -0,0 +1,172 @@
+//! Client-side prediction of our own commands.
+//!
+//! Our commands are executed locally right away and kept here until the server echoes
+//! them back. When a full game snapshot arrives, the game is rewound to it and the
+//! commands the server can't have seen yet are applied again on top of it, at the time
+//! they were executed. Commands still waiting in the outbound queue count as unseen.
+
+use std::collections::{HashMap, VecDeque};
+
+use super::*;
+
+/// Commands that aren't echoed back within this time were most likely rejected.
+const PENDING_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+
+struct PendingCmd {
+    executed_at: time::Duration,
+    /// `None` while the command waits in the outbound queue.
+    sent_at: Option<time::Duration>,
+    /// Serialized command, used to match it with its echo.
+    key: Vec<u8>,
+    cmd: GameCmd,
+}
+
+pub struct Prediction {
+    pending: VecDeque<PendingCmd>,
+    /// Estimated time for a command to reach the server.
+    pub latency: time::Duration,
+    /// How far objects jumped on the last reconciliations, not yet taken by the renderer.
+    corrections: HashMap<GameObjectId, Vec2>,
+}
+
+impl Prediction {
+    pub fn new() -> Self {
+        Self {
+            pending: VecDeque::new(),
+            latency: time::Duration::from_millis(100),
+            corrections: HashMap::new(),
+        }
+    }
+
+    /// Remembers commands that were just executed locally and queued for the server.
+    pub fn push(&mut self, cmds: &[GameCmd]) {
+        let executed_at = now();
+        self.expire(executed_at);
+        for cmd in cmds {
+            let Some(key) = key(cmd) else {
+                continue;
+            };
+            self.pending.push_back(PendingCmd {
+                executed_at,
+                sent_at: None,
+                key,
+                cmd: cmd.clone(),
+            });
+        }
+    }
+
+    /// Marks queued commands as sent, from now on the server may have seen them.
+    pub fn sent(&mut self, cmds: &[GameCmd]) {
+        let sent_at = now();
+        for cmd in cmds {
+            if let Some(i) = self.position(cmd, |pending| pending.sent_at.is_none()) {
+                self.pending[i].sent_at = Some(sent_at);
+            }
+        }
+    }
+
+    /// Forgets queued commands that were dropped instead of sent.
+    pub fn forget(&mut self, cmds: &[GameCmd]) {
+        for cmd in cmds {
+            if let Some(i) = self.position(cmd, |pending| pending.sent_at.is_none()) {
+                self.pending.remove(i);
+            }
+        }
+    }
+
+    /// Removes a command the server echoed back. Returns whether it was pending.
+    pub fn acknowledge(&mut self, cmd: &GameCmd) -> bool {
+        self.expire(now());
+        match self.position(cmd, |pending| pending.sent_at.is_some()) {
+            Some(i) => {
+                self.pending.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn position(&self, cmd: &GameCmd, filter: impl Fn(&PendingCmd) -> bool) -> Option<usize> {
+        let key = key(cmd)?;
+        self.pending
+            .iter()
+            .position(|pending| filter(pending) && pending.key == key)
+    }
+
+    /// Drops commands sent too long ago, queued ones are dropped by the queue itself.
+    fn expire(&mut self, now: time::Duration) {
+        self.pending.retain(|pending| {
+            pending
+                .sent_at
+                .is_none_or(|sent_at| now.saturating_sub(sent_at) <= PENDING_TIMEOUT)
+        });
+    }
+
+    /// Replaces `game` with the authoritative `snapshot` and re-applies the commands sent
+    /// after it was taken. Both games have to be in the client's clock.
+    ///
+    /// Returns the distance of the furthest jump, i.e. how far the local game had diverged.
+    pub fn reconcile(&mut self, game: &mut Game, snapshot: Game, user: User) -> f32 {
+        let previous_update = game.sync.last_update;
+        let previous_positions = game
+            .game_objects
+            .iter()
+            .map(|(id, game_object)| (*id, game_object.body().position))
+            .collect::<HashMap<_, _>>();
+
+        *game = snapshot;
+        // commands that reached the server before the snapshot are already in it
+        let snapshot_time = game.sync.last_update;
+        let latency = self.latency;
+        self.pending.retain(|pending| {
+            pending
+                .sent_at
+                .is_none_or(|sent_at| sent_at + latency > snapshot_time)
+        });
+        for pending in &self.pending {
+            update_until(game, pending.executed_at);
+            if let Err(e) = game.execute_cmd(user, pending.cmd.clone()) {
+                warn!("Predicted game cmd {:?} no longer executes: {:?}", pending.cmd, e);
+            }
+        }
+        update_until(game, previous_update);
+
+        let mut divergence = 0f32;
+        for (id, game_object) in &game.game_objects {
+            let Some(previous_position) = previous_positions.get(id) else {
+                continue;
+            };
+            let correction = *previous_position - game_object.body().position;
+            divergence = divergence.max(correction.length());
+            *self.corrections.entry(*id).or_insert(Vec2::ZERO) += correction;
+        }
+        divergence
+    }
+
+    /// Returns the position corrections since the last call, for the renderer to smooth out.
+    pub fn take_corrections(&mut self) -> HashMap<GameObjectId, Vec2> {
+        std::mem::take(&mut self.corrections)
+    }
+}
+
+/// Serialized command, `None` if it can't be serialized, which leaves it unpredicted.
+fn key(cmd: &GameCmd) -> Option<Vec<u8>> {
+    match serialize_bytes(cmd) {
+        Ok(key) => Some(key),
+        Err(e) => {
+            warn!("Failed to serialize game cmd {:?} for prediction: {:?}", cmd, e);
+            None
+        }
+    }
+}
+
+/// Advances the game to the given time of the client's clock.
+pub(crate) fn update_until(game: &mut Game, at: time::Duration) {
+    if at > game.sync.last_update {
+        game.update((at - game.sync.last_update).as_secs_f32());
+    }
+}
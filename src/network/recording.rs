@@ -0,0 +1,115 @@
+//! Recording of everything the server sends, for replaying sessions later.
+//!
+//! A recording starts with `SBREC` and a version byte, followed by one entry per
+//! received message: milliseconds since the recording started (`u64` LE), the length
+//! of the message (`u32` LE) and the message exactly as the server serialized it.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use super::*;
+
+const MAGIC: &[u8; 5] = b"SBREC";
+const VERSION: u8 = 1;
+
+/// Shared with the receive task, so a recording keeps going across reconnects.
+pub type Recorder = Arc<Mutex<Option<SessionRecorder>>>;
+
+pub struct SessionRecorder {
+    writer: BufWriter<File>,
+    started: Instant,
+}
+
+impl SessionRecorder {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        writer.flush()?;
+        Ok(Self {
+            writer,
+            started: Instant::now(),
+        })
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        let at = self.started.elapsed().as_millis() as u64;
+        self.writer.write_all(&at.to_le_bytes())?;
+        self.writer.write_all(&(data.len() as u32).to_le_bytes())?;
+        self.writer.write_all(data)?;
+        // flushed right away, so a killed client still leaves a readable recording
+        self.writer.flush()
+    }
+}
+
+/// Appends a received message to the recording, if one is running.
+/// A failed write stops the recording instead of the connection.
+pub fn record(recorder: &Recorder, data: &[u8]) {
+    let mut recorder = recorder.lock().unwrap();
+    let Some(session_recorder) = recorder.as_mut() else {
+        return;
+    };
+    if let Err(e) = session_recorder.write(data) {
+        error!("Failed to write recording, stopping it: {}", e);
+        *recorder = None;
+    }
+}
+
+pub struct RecordedResponse {
+    /// Time since the recording started.
+    pub at: time::Duration,
+    pub response: ServerResponse,
+}
+
+pub fn read_recording(path: &Path) -> io::Result<Vec<RecordedResponse>> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let mut header = [0; 6];
+    reader.read_exact(&mut header)?;
+    if &header[..5] != MAGIC {
+        return Err(invalid_data("not a stellar bit recording"));
+    }
+    if header[5] != VERSION {
+        return Err(invalid_data(format!("unsupported recording version {}", header[5])));
+    }
+
+    let mut responses = vec![];
+    loop {
+        let mut at = [0; 8];
+        match reader.read_exact(&mut at) {
+            Ok(()) => (),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        let mut len = [0; 4];
+        let mut data = vec![];
+        let entry = reader.read_exact(&mut len).and_then(|_| {
+            data.resize(u32::from_le_bytes(len) as usize, 0);
+            reader.read_exact(&mut data)
+        });
+        match entry {
+            Ok(()) => (),
+            // the client was probably killed while writing the last entry
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                warn!("Recording {:?} ends with an incomplete entry, ignoring it", path);
+                break;
+            }
+            Err(e) => return Err(e),
+        }
+
+        let response = deserialize_bytes(&data)
+            .map_err(|_| invalid_data("malformed message in recording"))?;
+        responses.push(RecordedResponse {
+            at: time::Duration::from_millis(u64::from_le_bytes(at)),
+            response,
+        });
+    }
+    Ok(responses)
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
@@ -0,0 +1,185 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use super::*;
+
+/// Seconds of history kept for the graphs.
+const HISTORY_LEN: usize = 60;
+const SAMPLE_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MessageCount {
+    pub messages: u64,
+    pub bytes: u64,
+}
+
+impl MessageCount {
+    fn add(&mut self, bytes: usize) {
+        self.messages += 1;
+        self.bytes += bytes as u64;
+    }
+}
+
+/// One value per second, oldest first.
+#[derive(Clone, Debug, Default)]
+pub struct StatsHistory {
+    pub sent_bytes: VecDeque<f32>,
+    pub received_bytes: VecDeque<f32>,
+    pub rtt_ms: VecDeque<f32>,
+    /// Size of the full games received in that second, 0 when there was none.
+    pub full_sync_bytes: VecDeque<f32>,
+}
+
+impl StatsHistory {
+    fn push(&mut self, sent: f32, received: f32, rtt_ms: f32, full_sync: f32) {
+        for (history, value) in [
+            (&mut self.sent_bytes, sent),
+            (&mut self.received_bytes, received),
+            (&mut self.rtt_ms, rtt_ms),
+            (&mut self.full_sync_bytes, full_sync),
+        ] {
+            history.push_back(value);
+            if history.len() > HISTORY_LEN {
+                history.pop_front();
+            }
+        }
+    }
+}
+
+/// Traffic of a `NetworkConnection`, counted per request and response variant.
+#[derive(Clone, Debug, Default)]
+pub struct NetworkStats {
+    pub sent: BTreeMap<&'static str, MessageCount>,
+    pub received: BTreeMap<&'static str, MessageCount>,
+    pub slow_downs: u64,
+    pub last_full_sync_bytes: usize,
+    pub max_full_sync_bytes: usize,
+    /// Round trip of the last clock sync.
+    pub rtt: time::Duration,
+    pub history: StatsHistory,
+    sample: Sample,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Sample {
+    started: time::Duration,
+    sent_bytes: usize,
+    received_bytes: usize,
+    full_sync_bytes: usize,
+}
+
+impl NetworkStats {
+    pub fn total_sent(&self) -> MessageCount {
+        total(&self.sent)
+    }
+
+    pub fn total_received(&self) -> MessageCount {
+        total(&self.received)
+    }
+
+    pub(super) fn record_sent(&mut self, request: &ClientRequest, bytes: usize) {
+        self.sent.entry(request_name(request)).or_default().add(bytes);
+        self.sample.sent_bytes += bytes;
+    }
+
+    /// `response` is `None` for frames that couldn't be decoded.
+    pub(super) fn record_received(&mut self, response: Option<&ServerResponse>, bytes: usize) {
+        let name = response.map_or("Malformed", response_name);
+        self.received.entry(name).or_default().add(bytes);
+        self.sample.received_bytes += bytes;
+        match response {
+            Some(ServerResponse::SyncFullGame(_)) => {
+                self.last_full_sync_bytes = bytes;
+                self.max_full_sync_bytes = self.max_full_sync_bytes.max(bytes);
+                self.sample.full_sync_bytes += bytes;
+            }
+            Some(ServerResponse::SlowDown) => self.slow_downs += 1,
+            _ => (),
+        }
+    }
+
+    /// Closes the current second of the history once it is over.
+    pub(super) fn tick(&mut self) {
+        let now = now();
+        if now.saturating_sub(self.sample.started) < SAMPLE_INTERVAL {
+            return;
+        }
+        let sample = std::mem::take(&mut self.sample);
+        self.history.push(
+            sample.sent_bytes as f32,
+            sample.received_bytes as f32,
+            self.rtt.as_secs_f32() * 1000.,
+            sample.full_sync_bytes as f32,
+        );
+        self.sample.started = now;
+    }
+}
+
+impl std::fmt::Display for NetworkStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let counts = |counts: &BTreeMap<&'static str, MessageCount>| {
+            counts
+                .iter()
+                .map(|(name, count)| format!("{} {} / {}", name, count.messages, kilobytes(count.bytes)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let (sent, received) = (self.total_sent(), self.total_received());
+        writeln!(
+            f,
+            "sent {} messages / {} ({})",
+            sent.messages,
+            kilobytes(sent.bytes),
+            counts(&self.sent)
+        )?;
+        writeln!(
+            f,
+            "received {} messages / {} ({})",
+            received.messages,
+            kilobytes(received.bytes),
+            counts(&self.received)
+        )?;
+        write!(
+            f,
+            "rtt {:.1} ms, {} slow downs, full syncs {} (max {})",
+            self.rtt.as_secs_f32() * 1000.,
+            self.slow_downs,
+            kilobytes(self.last_full_sync_bytes as u64),
+            kilobytes(self.max_full_sync_bytes as u64)
+        )
+    }
+}
+
+pub fn kilobytes(bytes: u64) -> String {
+    format!("{:.1} kB", bytes as f32 / 1000.)
+}
+
+fn total(counts: &BTreeMap<&'static str, MessageCount>) -> MessageCount {
+    counts.values().fold(MessageCount::default(), |total, count| MessageCount {
+        messages: total.messages + count.messages,
+        bytes: total.bytes + count.bytes,
+    })
+}
+
+fn request_name(request: &ClientRequest) -> &'static str {
+    match request {
+        ClientRequest::Join(..) => "Join",
+        ClientRequest::FullGameSync => "FullGameSync",
+        ClientRequest::GameCmdsSync => "GameCmdsSync",
+        ClientRequest::ExecuteGameCmds(_) => "ExecuteGameCmds",
+        ClientRequest::SyncClock => "SyncClock",
+        #[allow(unreachable_patterns)]
+        _ => "Other",
+    }
+}
+
+fn response_name(response: &ServerResponse) -> &'static str {
+    match response {
+        ServerResponse::SyncFullGame(_) => "SyncFullGame",
+        ServerResponse::SyncGameCmds(_) => "SyncGameCmds",
+        ServerResponse::SetUser(_) => "SetUser",
+        ServerResponse::SlowDown => "SlowDown",
+        ServerResponse::SyncClock(_) => "SyncClock",
+        #[allow(unreachable_patterns)]
+        _ => "Other",
+    }
+}
@@ -0,0 +1,169 @@
+use std::cmp::Ordering;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use super::*;
+
+/// How often the client asks the server for new commands and for the full game.
+/// The intervals adapt at runtime within these limits, see `SyncScheduler`.
+///
+/// Can be loaded from a TOML file, missing fields keep their defaults and unknown ones
+/// are rejected.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SyncPolicy {
+    pub cmds_sync_ms: u64,
+    pub full_sync_ms: u64,
+    pub min_full_sync_ms: u64,
+    pub max_full_sync_ms: u64,
+    /// Every `SlowDown` from the server multiplies all intervals by this.
+    pub slow_down_factor: f32,
+    pub max_slow_down: f32,
+    /// After this long without a `SlowDown`, the intervals are halved again.
+    pub recovery_ms: u64,
+    /// Objects jumping further than this on a full sync mean the local game diverged,
+    /// and full syncs are requested as often as allowed.
+    pub diverged_distance: f32,
+    /// If no object jumps further than this, the command syncs are keeping up
+    /// and the next full sync can wait twice as long.
+    pub in_sync_distance: f32,
+    pub rate_limit: RateLimit,
+}
+
+impl Default for SyncPolicy {
+    fn default() -> Self {
+        Self {
+            cmds_sync_ms: 300,
+            full_sync_ms: 3000,
+            min_full_sync_ms: 1000,
+            max_full_sync_ms: 30000,
+            slow_down_factor: 2.,
+            max_slow_down: 16.,
+            recovery_ms: 10000,
+            diverged_distance: 5.,
+            in_sync_distance: 0.5,
+            rate_limit: RateLimit::default(),
+        }
+    }
+}
+
+impl SyncPolicy {
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        let invalid = |reason: String| std::io::Error::new(std::io::ErrorKind::InvalidData, reason);
+        let text = std::fs::read_to_string(path)?;
+        let policy: Self = toml::from_str(&text).map_err(|e| invalid(e.to_string()))?;
+        policy.validate().map_err(invalid)?;
+        Ok(policy)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.cmds_sync_ms == 0 || self.full_sync_ms == 0 || self.min_full_sync_ms == 0 {
+            return Err("sync intervals have to be positive".into());
+        }
+        if self.min_full_sync_ms > self.max_full_sync_ms {
+            return Err("min_full_sync_ms is larger than max_full_sync_ms".into());
+        }
+        if !(1. ..).contains(&self.slow_down_factor) || !(1. ..).contains(&self.max_slow_down) {
+            return Err("slow down factors have to be at least 1".into());
+        }
+        let positive = |value: f32| value.partial_cmp(&0.) == Some(Ordering::Greater);
+        if !positive(self.rate_limit.messages_per_second) || !positive(self.rate_limit.bytes_per_second) {
+            return Err("rate limits have to be positive".into());
+        }
+        Ok(())
+    }
+}
+
+/// Decides when to send `GameCmdsSync` and `FullGameSync` following a `SyncPolicy`.
+pub struct SyncScheduler {
+    policy: SyncPolicy,
+    /// Multiplier of all intervals, raised by `SlowDown` responses.
+    slow_down: f32,
+    last_slow_down: time::Duration,
+    full_sync_ms: u64,
+    next_cmds_sync: time::Duration,
+    next_full_sync: time::Duration,
+}
+
+impl SyncScheduler {
+    pub fn new(policy: SyncPolicy) -> Self {
+        let now = now();
+        Self {
+            slow_down: 1.,
+            last_slow_down: now,
+            full_sync_ms: policy.full_sync_ms,
+            next_cmds_sync: now + time::Duration::from_millis(policy.cmds_sync_ms),
+            next_full_sync: now + time::Duration::from_millis(policy.full_sync_ms),
+            policy,
+        }
+    }
+
+    /// Returns the sync requests that are due.
+    pub fn poll(&mut self) -> Vec<ClientRequest> {
+        let now = now();
+        self.recover(now);
+
+        let mut requests = vec![];
+        if now >= self.next_cmds_sync {
+            requests.push(ClientRequest::GameCmdsSync);
+            self.next_cmds_sync = now + self.scaled(self.policy.cmds_sync_ms);
+        }
+        if now >= self.next_full_sync {
+            requests.push(ClientRequest::FullGameSync);
+            self.next_full_sync = now + self.scaled(self.full_sync_ms);
+        }
+        requests
+    }
+
+    pub fn on_slow_down(&mut self) {
+        let now = now();
+        self.slow_down = (self.slow_down * self.policy.slow_down_factor).min(self.policy.max_slow_down);
+        self.last_slow_down = now;
+        self.next_cmds_sync = self.next_cmds_sync.max(now + self.scaled(self.policy.cmds_sync_ms));
+        self.next_full_sync = self.next_full_sync.max(now + self.scaled(self.full_sync_ms));
+        warn!("Server asked to slow down, syncing {}x less often", self.slow_down);
+    }
+
+    /// Adapts the full sync interval to how far the local game was off when a full game
+    /// arrived. `divergence` is the distance of the furthest jump.
+    pub fn on_full_sync(&mut self, divergence: f32) {
+        self.full_sync_ms = if divergence > self.policy.diverged_distance {
+            self.policy.min_full_sync_ms
+        } else if divergence < self.policy.in_sync_distance {
+            (self.full_sync_ms * 2).min(self.policy.max_full_sync_ms)
+        } else {
+            self.policy.full_sync_ms
+        };
+        self.next_full_sync = now() + self.scaled(self.full_sync_ms);
+    }
+
+    fn recover(&mut self, now: time::Duration) {
+        if self.slow_down > 1.
+            && now.saturating_sub(self.last_slow_down)
+                > time::Duration::from_millis(self.policy.recovery_ms)
+        {
+            self.slow_down = (self.slow_down / 2.).max(1.);
+            self.last_slow_down = now;
+        }
+    }
+
+    fn scaled(&self, ms: u64) -> time::Duration {
+        time::Duration::from_millis(ms).mul_f32(self.slow_down)
+    }
+}
+
+impl std::fmt::Display for SyncScheduler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "commands every {:?}, full game every {:?}",
+            self.scaled(self.policy.cmds_sync_ms),
+            self.scaled(self.full_sync_ms)
+        )?;
+        if self.slow_down > 1. {
+            write!(f, " (slowed down {}x)", self.slow_down)?;
+        }
+        Ok(())
+    }
+}
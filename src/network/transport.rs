@@ -0,0 +1,127 @@
+//! What a `NetworkConnection` needs from the link to a server.
+//!
+//! A `Connector` opens a `Transport` for every connection attempt, including reconnects.
+//! The transport only moves requests and frames, everything it receives is passed to its
+//! `TransportHandle` and handled the same way for every transport.
+
+use super::*;
+
+/// `Send` on desktop, where the connection may be moved between threads. Browser
+/// objects can't be, so nothing is required on the web.
+#[cfg(not(target_arch = "wasm32"))]
+pub trait MaybeSend: Send {}
+#[cfg(not(target_arch = "wasm32"))]
+impl<T: Send> MaybeSend for T {}
+#[cfg(target_arch = "wasm32")]
+pub trait MaybeSend {}
+#[cfg(target_arch = "wasm32")]
+impl<T> MaybeSend for T {}
+
+/// An open connection to a server.
+pub trait Transport: MaybeSend {
+    /// Only fails when the connection is gone, a request that can't be sent for another
+    /// reason is reported through the handle and skipped.
+    fn send_multiple(&mut self, msgs: Vec<ClientRequest>) -> Result<(), ConnectionError>;
+
+    /// Called every tick by `NetworkConnection::poll`, for transports without tasks of their own.
+    fn poll(&mut self) {}
+
+    /// Closes the connection on purpose, nothing is reported to the handle afterwards.
+    fn close(self: Box<Self>);
+}
+
+/// A connection attempt, polled until it is finished.
+pub trait PendingTransport: MaybeSend {
+    fn is_finished(&self) -> bool;
+
+    /// Check `is_finished` first, an unfinished attempt may block or count as failed.
+    fn finish(self: Box<Self>) -> Result<Box<dyn Transport>, ConnectionError>;
+}
+
+/// Opens the transports of a `NetworkConnection`.
+pub trait Connector: MaybeSend {
+    fn connect(&self, handle: TransportHandle) -> Box<dyn PendingTransport>;
+}
+
+/// A connection that was made right away, e.g. to an in-process server.
+pub struct Ready(pub Result<Box<dyn Transport>, ConnectionError>);
+
+impl PendingTransport for Ready {
+    fn is_finished(&self) -> bool {
+        true
+    }
+
+    fn finish(self: Box<Self>) -> Result<Box<dyn Transport>, ConnectionError> {
+        self.0
+    }
+}
+
+/// How a transport reports back to its `NetworkConnection`. Each connection attempt gets
+/// its own, so nothing from an old transport is mistaken for the current one.
+#[derive(Clone)]
+pub struct TransportHandle {
+    shared: Shared,
+    events: mpsc::Sender<NetworkEvent>,
+}
+
+impl TransportHandle {
+    pub(super) fn new(shared: Shared, events: mpsc::Sender<NetworkEvent>) -> Self {
+        Self { shared, events }
+    }
+
+    /// Handles a frame from the server. A malformed one is reported and skipped.
+    pub fn receive(&self, data: &[u8]) {
+        if let Err(e) = receive_data(data, &self.shared) {
+            self.error(e);
+        }
+    }
+
+    /// Counts a request that was sent, `bytes` being its serialized size.
+    pub fn sent(&self, request: &ClientRequest, bytes: usize) {
+        self.shared.stats.lock().unwrap().record_sent(request, bytes);
+    }
+
+    /// The connection was lost, a reconnect is attempted.
+    pub fn disconnected(&self, reason: impl Into<String>) {
+        let _ = self.events.send(NetworkEvent::Disconnected(reason.into()));
+    }
+
+    /// The server closed the connection on purpose, no reconnect is attempted.
+    pub fn kicked(&self, reason: impl Into<String>) {
+        let _ = self.events.send(NetworkEvent::Kicked(reason.into()));
+    }
+
+    /// An error the connection survived.
+    pub fn error(&self, error: ConnectionError) {
+        let _ = self.events.send(NetworkEvent::Error(error));
+    }
+}
+
+/// Connects over a websocket, with the client of the current platform.
+pub struct WebSocketConnector {
+    server_addr: String,
+    #[cfg(not(target_arch = "wasm32"))]
+    runtime: tokio::runtime::Handle,
+}
+
+impl WebSocketConnector {
+    /// On desktop this has to be called within the tokio runtime the connection runs on.
+    pub fn new(server_addr: String) -> Self {
+        Self {
+            server_addr,
+            #[cfg(not(target_arch = "wasm32"))]
+            runtime: tokio::runtime::Handle::current(),
+        }
+    }
+}
+
+impl Connector for WebSocketConnector {
+    fn connect(&self, handle: TransportHandle) -> Box<dyn PendingTransport> {
+        let server_addr = self.server_addr.clone();
+        #[cfg(not(target_arch = "wasm32"))]
+        let connect_task = ConnectTask::spawn(&self.runtime, server_addr, handle);
+        #[cfg(target_arch = "wasm32")]
+        let connect_task = ConnectTask::spawn(server_addr, handle);
+        Box::new(connect_task)
+    }
+}
@@ -1,94 +1,188 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
 use super::*;
 use log::warn;
 use wasm_bindgen_futures::JsFuture;
-use web_sys::{ErrorEvent, MessageEvent, WebSocket};
+use web_sys::{BinaryType, CloseEvent, ErrorEvent, MessageEvent, WebSocket};
+
+/// The close code of a kick, `CloseCode::Policy` on desktop. Servers and proxies close with
+/// any other code on a restart, so those are reconnected after.
+const KICK_CLOSE_CODE: u16 = 1008;
 
 pub struct WebNetworkClient {
     ws: WebSocket,
-    time_delay: Arc<RwLock<i64>>,
+    handle: TransportHandle,
+    // kept alive as long as the socket may call them, and freed with the client
+    _onmessage: Closure<dyn FnMut(MessageEvent)>,
+    _onerror: Closure<dyn FnMut(ErrorEvent)>,
+    _onclose: Closure<dyn FnMut(CloseEvent)>,
 }
 
 impl WebNetworkClient {
     pub async fn connect(
         server_addr: &str,
-        game: Arc<RwLock<Game>>,
-        user: Arc<RwLock<User>>,
-    ) -> Result<Self, NetworkError> {
+        handle: TransportHandle,
+    ) -> Result<Self, ConnectionError> {
         warn!("Connecting to server at {}", server_addr);
-        let promise = js_sys::Promise::new(&mut |resolve, reject| {
-            let ws = WebSocket::new(server_addr).unwrap();
-            let cloned_ws = ws.clone();
+        let connect_error = |reason: String| ConnectionError::Connect {
+            addr: server_addr.into(),
+            reason,
+        };
+        let ws = WebSocket::new(server_addr).map_err(|e| connect_error(js_error(&e)))?;
+        // frames arrive as ArrayBuffers, so they can be read synchronously
+        ws.set_binary_type(BinaryType::Arraybuffer);
 
-            let on_open_callback = Closure::wrap(Box::new(move || {
-                resolve.call1(&JsValue::NULL, &cloned_ws);
-            }) as Box<dyn Fn()>);
+        let promise = {
+            let ws = ws.clone();
+            js_sys::Promise::new(&mut |resolve, reject| {
+                let on_open_callback = Closure::wrap(Box::new(move || {
+                    let _ = resolve.call0(&JsValue::NULL);
+                }) as Box<dyn Fn()>);
 
-            let on_error_callback = Closure::wrap(Box::new(move |event: ErrorEvent| {
-                reject.call1(&JsValue::NULL, &event);
-            }) as Box<dyn Fn(ErrorEvent)>);
+                let on_error_callback = Closure::wrap(Box::new(move |event: ErrorEvent| {
+                    let _ = reject.call1(&JsValue::NULL, &event);
+                }) as Box<dyn Fn(ErrorEvent)>);
 
-            ws.set_onopen(Some(on_open_callback.as_ref().unchecked_ref()));
-            ws.set_onerror(Some(on_error_callback.as_ref().unchecked_ref()));
-
-            on_open_callback.forget();
-            on_error_callback.forget();
-        });
+                ws.set_onopen(Some(on_open_callback.as_ref().unchecked_ref()));
+                ws.set_onerror(Some(on_error_callback.as_ref().unchecked_ref()));
 
-        let ws = JsFuture::from(promise)
+                on_open_callback.forget();
+                on_error_callback.forget();
+            })
+        };
+        JsFuture::from(promise)
             .await
-            .map(|value| value.dyn_into::<WebSocket>().unwrap())
-            .map_err(|value| value.dyn_into::<ErrorEvent>().unwrap())
-            .unwrap();
-
-        let time_delay = Arc::new(RwLock::new(0));
-        let time_delay_clone = time_delay.clone();
-        let onmessage_callback = Closure::<dyn FnMut(_)>::new(move |e: MessageEvent| {
-            if let Ok(abuf) = e.data().dyn_into::<js_sys::ArrayBuffer>() {
-            } else if let Ok(txt) = e.data().dyn_into::<js_sys::JsString>() {
-                warn!("Received text");
-            } else if let Ok(blob) = e.data().dyn_into::<web_sys::Blob>() {
-                // better alternative to juggling with FileReader is to use https://crates.io/crates/gloo-file
-                let fr = web_sys::FileReader::new().unwrap();
-                let fr_c = fr.clone();
-                // create onLoadEnd callback
-                let game = game.clone();
-                let time_delay = time_delay_clone.clone();
-                let user = user.clone();
-                let onloadend_cb =
-                    Closure::<dyn FnMut(_)>::new(move |_e: web_sys::ProgressEvent| {
-                        let array = js_sys::Uint8Array::new(&fr_c.result().unwrap());
-                        let response = deserialize_bytes(&array.to_vec()).unwrap();
-                        handle_server_response(
-                            response,
-                            game.clone(),
-                            time_delay.clone(),
-                            user.clone(),
-                        );
-                    });
-                fr.set_onloadend(Some(onloadend_cb.as_ref().unchecked_ref()));
-                fr.read_as_array_buffer(&blob).expect("blob not readable");
-                onloadend_cb.forget();
-            } else {
-                warn!("Received unknown message type");
-            }
-        });
+            .map_err(|e| connect_error(js_error(&e)))?;
+        ws.set_onopen(None);
 
+        let onmessage_callback = {
+            let handle = handle.clone();
+            Closure::<dyn FnMut(_)>::new(move |e: MessageEvent| {
+                if let Ok(abuf) = e.data().dyn_into::<js_sys::ArrayBuffer>() {
+                    handle.receive(&js_sys::Uint8Array::new(&abuf).to_vec());
+                } else if e.data().is_string() {
+                    handle.error(ConnectionError::Decode("unexpected text frame".into()));
+                } else {
+                    handle.error(ConnectionError::Decode("unknown message type".into()));
+                }
+            })
+        };
         ws.set_onmessage(Some(onmessage_callback.as_ref().unchecked_ref()));
-        // forget the callback to keep it alive
-        onmessage_callback.forget();
-        Ok(Self { ws, time_delay })
+
+        // the close event that follows tells whether the connection is gone
+        let onerror_callback = {
+            let handle = handle.clone();
+            Closure::<dyn FnMut(_)>::new(move |e: ErrorEvent| {
+                handle.error(ConnectionError::Receive(js_error(&e)));
+            })
+        };
+        ws.set_onerror(Some(onerror_callback.as_ref().unchecked_ref()));
+
+        let onclose_callback = {
+            let handle = handle.clone();
+            Closure::<dyn FnMut(_)>::new(move |e: CloseEvent| {
+                let reason = format!("closed by server ({}: {})", e.code(), e.reason());
+                if e.code() == KICK_CLOSE_CODE {
+                    handle.kicked(reason);
+                } else {
+                    handle.disconnected(reason);
+                }
+            })
+        };
+        ws.set_onclose(Some(onclose_callback.as_ref().unchecked_ref()));
+        Ok(Self {
+            ws,
+            handle,
+            _onmessage: onmessage_callback,
+            _onerror: onerror_callback,
+            _onclose: onclose_callback,
+        })
     }
 
-    pub fn send(&self, msg: ClientRequest) -> Result<(), NetworkError> {
-        let msg_raw = serialize_bytes(&msg).map_err(|_| NetworkError::IncorrectDataFormat)?;
-        self.ws.send_with_u8_array(&msg_raw).unwrap();
+    fn send(&self, msg: ClientRequest) -> Result<(), ConnectionError> {
+        let msg_raw =
+            serialize_bytes(&msg).map_err(|e| ConnectionError::Encode(format!("{:?}", e)))?;
+        self.ws
+            .send_with_u8_array(&msg_raw)
+            .map_err(|e| ConnectionError::Send(js_error(&e)))?;
+        self.handle.sent(&msg, msg_raw.len());
         Ok(())
     }
+}
 
-    pub fn send_multiple(&self, msgs: Vec<ClientRequest>) -> Result<(), NetworkError> {
+impl Transport for WebNetworkClient {
+    /// Skips requests that can't be encoded, only a failing socket is an error.
+    fn send_multiple(&mut self, msgs: Vec<ClientRequest>) -> Result<(), ConnectionError> {
         for msg in msgs {
-            self.send(msg)?;
+            match self.send(msg) {
+                Err(e @ ConnectionError::Encode(_)) => self.handle.error(e),
+                result => result?,
+            }
         }
         Ok(())
     }
+
+    /// Closes the socket without reporting it as a disconnect, the close was on purpose.
+    fn close(self: Box<Self>) {
+        // dropping does it
+    }
+}
+
+impl Drop for WebNetworkClient {
+    /// Detaches the callbacks before closing, so a replaced connection neither reports
+    /// the close nor applies frames still arriving to the game next to the new one.
+    fn drop(&mut self) {
+        self.ws.set_onmessage(None);
+        self.ws.set_onclose(None);
+        self.ws.set_onerror(None);
+        let _ = self.ws.close();
+    }
+}
+
+/// A connection attempt running in the background on the browser's event loop.
+pub struct ConnectTask {
+    result: Rc<RefCell<Option<Result<WebNetworkClient, ConnectionError>>>>,
+}
+
+impl ConnectTask {
+    pub fn spawn(server_addr: String, handle: TransportHandle) -> Self {
+        let result = Rc::new(RefCell::new(None));
+        let task_result = result.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            let client = WebNetworkClient::connect(&server_addr, handle).await;
+            // nobody is waiting for the connection anymore
+            if Rc::strong_count(&task_result) == 1 {
+                // dropping the client closes it
+                drop(client);
+                return;
+            }
+            *task_result.borrow_mut() = Some(client);
+        });
+        Self { result }
+    }
+}
+
+impl PendingTransport for ConnectTask {
+    fn is_finished(&self) -> bool {
+        self.result.borrow().is_some()
+    }
+
+    /// An unfinished attempt counts as failed.
+    fn finish(self: Box<Self>) -> Result<Box<dyn Transport>, ConnectionError> {
+        let client = self
+            .result
+            .borrow_mut()
+            .take()
+            .unwrap_or(Err(ConnectionError::Closed))?;
+        Ok(Box::new(client))
+    }
+}
+
+/// Best effort description of a JS exception or error event.
+fn js_error(value: &JsValue) -> String {
+    if let Some(event) = value.dyn_ref::<ErrorEvent>() {
+        return event.message();
+    }
+    value.as_string().unwrap_or_else(|| format!("{:?}", value))
 }
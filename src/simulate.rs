@@ -0,0 +1,214 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
+
+use super::*;
+
+const STAR_BASE_DISTANCE: f32 = 5000.;
+const ASTEROIDS: usize = 300;
+const STARTING_MATERIALS: f32 = 2000.;
+
+/// A local match between computers, without any server.
+#[derive(Clone, Debug)]
+pub struct SimulationOptions {
+    /// One computer per player, player `i` is driven by `computers[i]`.
+    pub computers: Vec<ComputerSpec>,
+    /// Game time advanced by every step, in seconds.
+    pub dt: f32,
+    /// Game time after which the match ends without a winner.
+    pub duration: time::Duration,
+    pub isolated: bool,
+    pub budget: ExecutionBudget,
+    /// Places the asteroids, the same seed gives the same starting game.
+    pub seed: u64,
+}
+
+impl SimulationOptions {
+    pub fn new(computers: Vec<ComputerSpec>) -> Self {
+        Self {
+            computers,
+            dt: 0.05,
+            duration: time::Duration::from_secs(300),
+            isolated: false,
+            budget: ExecutionBudget::default(),
+            seed: rand::random(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PlayerReport {
+    pub player_id: u64,
+    pub computer: String,
+    pub star_bases: usize,
+    pub spacecrafts: usize,
+    pub materials: f32,
+    pub cmds: u64,
+    pub max_execution_ms: f32,
+    pub budget_overruns: u32,
+    pub suspended: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SimulationReport {
+    /// The only player with a star base left, `None` if the time ran out first.
+    pub winner: Option<u64>,
+    /// Replays the same starting game with `--seed`.
+    pub seed: u64,
+    pub game_time_secs: f32,
+    pub steps: u64,
+    pub players: Vec<PlayerReport>,
+}
+
+impl SimulationReport {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+struct SimulatedPlayer {
+    id: u64,
+    controller: Controller,
+    cmds: u64,
+}
+
+/// Plays a match between the given computers at a fixed `dt` until one star base
+/// is left or `duration` of game time passes.
+pub fn simulate(options: SimulationOptions) -> Result<SimulationReport, HeadlessError> {
+    let mut players = vec![];
+    for (i, spec) in options.computers.into_iter().enumerate() {
+        let mut controller = Controller::new();
+        controller.budget = options.budget.clone();
+        controller.hot_reload = false;
+        let (name, path) = (spec.name.clone(), spec.path.clone());
+        controller
+            .set_isolated(options.isolated)
+            .and_then(|_| controller.add_computer(spec))
+            .map_err(|e| HeadlessError::ComputerLoad(format!("'{}' ({:?}): {}", name, path, e)))?;
+        players.push(SimulatedPlayer {
+            id: i as u64,
+            controller,
+            cmds: 0,
+        });
+    }
+
+    let mut game = new_match(players.len() as u64, options.seed);
+    let egui_context = egui::Context::default();
+    let steps = (options.duration.as_secs_f32() / options.dt).ceil() as u64;
+
+    let mut step = 0;
+    while step < steps {
+        game.update(options.dt);
+        for player in &mut players {
+            let user = User::Player(player.id);
+            let cmds = player.controller.retrieve_cmds(&mut game, &user, &egui_context);
+            player.cmds += cmds.len() as u64;
+        }
+        step += 1;
+
+        if players.len() > 1 && players_with_star_base(&game).len() <= 1 {
+            break;
+        }
+    }
+
+    let remaining = players_with_star_base(&game);
+    let winner = match remaining.as_slice() {
+        [winner] if players.len() > 1 => Some(*winner),
+        _ => None,
+    };
+
+    Ok(SimulationReport {
+        winner,
+        seed: options.seed,
+        game_time_secs: step as f32 * options.dt,
+        steps: step,
+        players: players
+            .iter()
+            .map(|player| player_report(&game, player))
+            .collect(),
+    })
+}
+
+/// Every player gets a star base on a circle around the center and the same materials
+/// as the local sandbox, with asteroids in between placed by `seed`.
+pub fn new_match(player_count: u64, seed: u64) -> Game {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut game = Game::new();
+    for player_id in 0..player_count {
+        let angle = player_id as f32 / player_count as f32 * std::f32::consts::TAU;
+        let position = vec2(angle.cos(), angle.sin()) * STAR_BASE_DISTANCE;
+        game.execute_cmd(User::Server, GameCmd::AddPlayer(player_id)).unwrap();
+        game.execute_cmd(User::Server, GameCmd::SpawnStarBase(player_id, position, Vec2::ZERO))
+            .unwrap();
+        game.execute_cmd(
+            User::Server,
+            GameCmd::GiveMaterials(
+                player_id,
+                [
+                    Material::Iron,
+                    Material::Nickel,
+                    Material::Silicates,
+                    Material::Copper,
+                    Material::Carbon,
+                ]
+                .into_iter()
+                .map(|material| (material, STARTING_MATERIALS))
+                .collect(),
+            ),
+        )
+        .unwrap();
+    }
+    for _ in 0..ASTEROIDS {
+        game.execute_cmd(
+            User::Server,
+            GameCmd::SpawnRandomAsteroid(
+                random_unit_circle(&mut rng) * 10000.,
+                random_unit_circle(&mut rng) * 10.,
+            ),
+        )
+        .unwrap();
+    }
+    game
+}
+
+fn random_unit_circle(rng: &mut StdRng) -> Vec2 {
+    Vec2::from_angle(rng.gen_range(0. ..std::f32::consts::TAU))
+}
+
+fn players_with_star_base(game: &Game) -> Vec<u64> {
+    let mut players = game
+        .game_objects
+        .values()
+        .filter(|game_object| matches!(game_object, GameObject::StarBase(_)))
+        .filter_map(|game_object| game_object.owner())
+        .collect::<Vec<_>>();
+    players.sort();
+    players.dedup();
+    players
+}
+
+fn player_report(game: &Game, player: &SimulatedPlayer) -> PlayerReport {
+    let owned = |kind: fn(&GameObject) -> bool| {
+        game.game_objects
+            .values()
+            .filter(|game_object| game_object.owner() == Some(player.id) && kind(game_object))
+            .count()
+    };
+    let slot = &player.controller.slots()[0];
+    let stats = slot.stats();
+    PlayerReport {
+        player_id: player.id,
+        computer: slot.name.clone(),
+        star_bases: owned(|game_object| matches!(game_object, GameObject::StarBase(_))),
+        spacecrafts: owned(|game_object| matches!(game_object, GameObject::Spacecraft(_))),
+        materials: game
+            .players
+            .get(&player.id)
+            .map(|player| player.materials.values().sum::<f32>())
+            .unwrap_or(0.),
+        cmds: player.cmds,
+        max_execution_ms: stats.max.as_secs_f32() * 1000.,
+        budget_overruns: stats.overruns,
+        suspended: stats.suspended,
+    }
+}
@@ -0,0 +1,48 @@
+use std::time::Duration;
+
+use stellar_bit_client::{new_match, ConnectionState, HeadlessOptions, HeadlessSession, LoopbackServer};
+
+/// Steps the session until `done` holds, panicking after a few seconds.
+fn step_until(session: &mut HeadlessSession, done: impl Fn(&HeadlessSession) -> bool) {
+    for _ in 0..500 {
+        session.step().unwrap();
+        if done(session) {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    panic!("gave up waiting, connection state: {}", session.connection_state());
+}
+
+#[test]
+fn join_full_sync_and_clock_sync_round_trip() {
+    let server = LoopbackServer::new(new_match(2, 1));
+    // not in the match yet, the server adds the player on join
+    let mut session = HeadlessSession::loopback(&server, 2, HeadlessOptions::new(vec![])).unwrap();
+    assert_eq!(server.client_count(), 1);
+
+    step_until(&mut session, |session| {
+        let received = session.network_stats().received;
+        ["SetUser", "SyncFullGame", "SyncClock"]
+            .iter()
+            .all(|response| received.contains_key(response))
+    });
+
+    assert_eq!(session.connection_state(), &ConnectionState::Connected);
+    let game = session.game();
+    let game = game.read().unwrap();
+    assert!(game.players.contains_key(&2));
+    assert!(!game.game_objects.is_empty());
+    drop(game);
+    assert!(session.clock_stats().samples > 0);
+}
+
+#[test]
+fn dropping_the_session_leaves_the_server() {
+    let server = LoopbackServer::new(new_match(2, 1));
+    let session = HeadlessSession::loopback(&server, 0, HeadlessOptions::new(vec![])).unwrap();
+    assert_eq!(server.client_count(), 1);
+
+    drop(session);
+    assert_eq!(server.client_count(), 0);
+}
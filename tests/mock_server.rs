@@ -0,0 +1,192 @@
+use std::time::{Duration, Instant};
+
+use stellar_bit_client::{
+    exit_code, new_match, run_headless_join, ConnectionState, HeadlessOptions, HeadlessSession,
+    MockFaults, MockServer, SyncPolicy,
+};
+use stellar_bit_core::prelude::User;
+use tokio::runtime::Runtime;
+
+/// The mock server runs on a runtime of its own, the session blocks on its own one.
+fn mock_server(faults: MockFaults) -> (Runtime, MockServer) {
+    let rt = Runtime::new().unwrap();
+    let server = rt
+        .block_on(MockServer::bind("127.0.0.1:0", new_match(2, 1), faults))
+        .unwrap();
+    (rt, server)
+}
+
+fn join(server: &MockServer, options: HeadlessOptions) -> HeadlessSession {
+    HeadlessSession::from_join_string(&format!("{} mock 0", server.url()), options).unwrap()
+}
+
+/// Steps the session until `done` holds, panicking after ten seconds.
+fn step_until(session: &mut HeadlessSession, mut done: impl FnMut(&HeadlessSession) -> bool) {
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(10) {
+        session.step().unwrap();
+        if done(session) {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    panic!("gave up waiting, connection state: {}", session.connection_state());
+}
+
+/// Steps the session for `duration`, whatever happens.
+fn step_for(session: &mut HeadlessSession, duration: Duration) {
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        session.step().unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn received(session: &HeadlessSession, response: &str) -> u64 {
+    session
+        .network_stats()
+        .received
+        .get(response)
+        .map_or(0, |count| count.messages)
+}
+
+fn sent(session: &HeadlessSession, request: &str) -> u64 {
+    session
+        .network_stats()
+        .sent
+        .get(request)
+        .map_or(0, |count| count.messages)
+}
+
+fn wait_for_join(session: &mut HeadlessSession) {
+    step_until(session, |session| {
+        session.connection_state() == &ConnectionState::Connected
+            && received(session, "SetUser") > 0
+            && received(session, "SyncFullGame") > 0
+    });
+}
+
+/// Steps until the connection went through `Reconnecting` and joined again.
+fn wait_for_reconnect(session: &mut HeadlessSession) {
+    let joins = received(session, "SetUser");
+    let mut reconnecting = false;
+    step_until(session, |session| {
+        reconnecting |= matches!(session.connection_state(), ConnectionState::Reconnecting { .. });
+        reconnecting
+            && session.connection_state() == &ConnectionState::Connected
+            && received(session, "SetUser") > joins
+    });
+}
+
+#[test]
+fn reconnects_after_disconnect_all() {
+    let (_rt, server) = mock_server(MockFaults::default());
+    let mut session = join(&server, HeadlessOptions::new(vec![]));
+    wait_for_join(&mut session);
+
+    server.disconnect_all();
+    wait_for_reconnect(&mut session);
+    assert_eq!(server.server().client_count(), 1);
+}
+
+#[test]
+fn reconnects_after_close_after_ms() {
+    let (_rt, server) = mock_server(MockFaults::default());
+    let mut session = join(&server, HeadlessOptions::new(vec![]));
+    wait_for_join(&mut session);
+
+    server.set_faults(MockFaults {
+        close_after_ms: Some(300),
+        ..Default::default()
+    });
+    wait_for_reconnect(&mut session);
+
+    server.set_faults(MockFaults::default());
+    step_until(&mut session, |session| session.connection_state() == &ConnectionState::Connected);
+    step_for(&mut session, Duration::from_millis(500));
+    assert_eq!(session.connection_state(), &ConnectionState::Connected);
+}
+
+#[test]
+fn syncs_less_often_after_slow_down() {
+    let (_rt, server) = mock_server(MockFaults::default());
+    let mut options = HeadlessOptions::new(vec![]);
+    options.sync_policy = SyncPolicy {
+        cmds_sync_ms: 50,
+        ..Default::default()
+    };
+    let mut session = join(&server, options);
+    wait_for_join(&mut session);
+
+    let before = sent(&session, "GameCmdsSync");
+    step_for(&mut session, Duration::from_secs(1));
+    let normal = sent(&session, "GameCmdsSync") - before;
+
+    server.set_faults(MockFaults {
+        slow_down_every: Some(2),
+        ..Default::default()
+    });
+    step_until(&mut session, |session| session.network_stats().slow_downs >= 3);
+    server.set_faults(MockFaults::default());
+
+    let before = sent(&session, "GameCmdsSync");
+    step_for(&mut session, Duration::from_secs(1));
+    let slowed_down = sent(&session, "GameCmdsSync") - before;
+    assert!(
+        slowed_down * 2 < normal,
+        "{} command syncs per second after slowing down, {} before",
+        slowed_down,
+        normal
+    );
+}
+
+#[test]
+fn survives_dropped_responses() {
+    let (_rt, server) = mock_server(MockFaults::default());
+    let mut session = join(&server, HeadlessOptions::new(vec![]));
+    wait_for_join(&mut session);
+
+    server.set_faults(MockFaults {
+        drop_rate: 1.,
+        ..Default::default()
+    });
+    // answers already on their way may still arrive
+    step_for(&mut session, Duration::from_millis(200));
+    let (sent_before, received_before) = (sent(&session, "GameCmdsSync"), received(&session, "SyncGameCmds"));
+    step_for(&mut session, Duration::from_secs(1));
+    assert!(sent(&session, "GameCmdsSync") > sent_before);
+    assert_eq!(received(&session, "SyncGameCmds"), received_before);
+
+    server.set_faults(MockFaults::default());
+    step_until(&mut session, |session| received(session, "SyncGameCmds") > received_before);
+    assert_eq!(session.connection_state(), &ConnectionState::Connected);
+}
+
+#[test]
+fn spectates_headless() {
+    let (_rt, server) = mock_server(MockFaults::default());
+    let mut session = HeadlessSession::spectate(server.url(), HeadlessOptions::new(vec![])).unwrap();
+    step_until(&mut session, |session| received(session, "SyncFullGame") > 0);
+
+    assert_eq!(session.user(), User::Spectator);
+    assert_eq!(sent(&session, "Join"), 0);
+    assert_eq!(server.server().client_count(), 1);
+}
+
+#[test]
+fn headless_join_exit_codes() {
+    let (_rt, server) = mock_server(MockFaults::default());
+    let mut session = join(&server, HeadlessOptions::new(vec![]));
+    wait_for_join(&mut session);
+    assert_eq!(session.user(), User::Player(0));
+    session.shutdown();
+
+    let error = run_headless_join("not-a-join-string", HeadlessOptions::new(vec![])).unwrap_err();
+    assert_eq!(error.exit_code(), exit_code::USAGE);
+
+    let addr = server.url();
+    drop(server);
+    let error = run_headless_join(&format!("{} mock 0", addr), HeadlessOptions::new(vec![]))
+        .unwrap_err();
+    assert_eq!(error.exit_code(), exit_code::CONNECTION_FAILED);
+}